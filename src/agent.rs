@@ -1,50 +1,892 @@
 use once_cell::sync::Lazy;
 
-#[cfg(feature = "tls")]
+use std::collections::HashSet;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
+use crate::cache::Cache;
+#[cfg(feature = "cookies")]
+use crate::cookies::{CookieStore, CookieStoreGuard};
+use crate::download;
 use crate::error::Error;
+use crate::middleware::Middleware;
+use crate::multiple;
+use crate::pool::ConnectionPool;
+use crate::proxy::Proxy;
 use crate::request::Request;
-use crate::response::Response;
+use crate::resolve::Resolver;
+use crate::response::{Response, MAX_HEAD_SIZE};
+use crate::retry::RetryPolicy;
+use crate::sign::{SignFn, SignRequest};
+use crate::stream::SocketOptions;
+#[cfg(feature = "testing")]
+use crate::testing::Mock;
+#[cfg(feature = "tls")]
+use crate::tls::{client_config, verifier_client_config, Certificate, NoVerifier, TLS_CONFIG};
+use crate::unit;
 use crate::url::Url;
+use crate::wire::{Wire, WireLog};
 
-pub type Result<T> = std::result::Result<T, Error>;
-
-static USER_AGENT: Lazy<Agent> = Lazy::new(|| {
-    #[cfg(feature = "tls")]
-    let tls_config = {
-        let mut root_store = rustls::RootCertStore::empty();
-        root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
-            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
-                ta.subject,
-                ta.spki,
-                ta.name_constraints,
-            )
-        }));
-
-        let config = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
-        Arc::new(config)
-    };
-    Agent {
-        user_agent: "ureq/2.3.1",
-        #[cfg(feature = "tls")]
-        tls_config,
+pub(crate) static DEFAULT_AGENT: Lazy<Agent> = Lazy::new(|| AgentBuilder::new().build());
+
+type RedirectFn = dyn Fn(&Url, &Url) -> bool + Send + Sync;
+pub(crate) type UnauthorizedFn = dyn Fn(&Response) -> Option<String> + Send + Sync;
+
+/// Decides which redirects a request follows.
+///
+/// `301`, `302` and `303` redirects are followed with a `GET` (a `HEAD`
+/// stays `HEAD`) and without the body. `307` and `308` redirects keep the
+/// method and send the body again. A body sent from a reader, with
+/// [`Request::send()`](crate::Request::send), can't be sent again.
+/// Following such a redirect is an
+/// [`ErrorKind::BodyNotReplayable`](crate::ErrorKind::BodyNotReplayable)
+/// error.
+#[derive(Clone)]
+pub enum RedirectPolicy {
+    /// Return redirects as the response.
+    None,
+    /// Follow up to this many redirects. One more is an
+    /// [`ErrorKind::TooManyRedirects`](crate::ErrorKind::TooManyRedirects) error.
+    Limited(u32),
+    /// Ask the closure, given the current and the next url, whether to
+    /// follow. Returning `false` makes the redirect the response.
+    ///
+    /// There is no hop limit on top of this, the closure has to stop loops.
+    ///
+    /// ```
+    /// use ureq::RedirectPolicy;
+    ///
+    /// // stay on the same host
+    /// let policy = RedirectPolicy::custom(|from, to| from.host_str() == to.host_str());
+    /// ```
+    Custom(Arc<RedirectFn>),
+}
+
+impl RedirectPolicy {
+    /// A [`RedirectPolicy::Custom`] from a closure.
+    pub fn custom(f: impl Fn(&Url, &Url) -> bool + Send + Sync + 'static) -> Self {
+        RedirectPolicy::Custom(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for RedirectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RedirectPolicy::None => write!(f, "None"),
+            RedirectPolicy::Limited(n) => write!(f, "Limited({})", n),
+            RedirectPolicy::Custom(_) => write!(f, "Custom(..)"),
+        }
     }
-});
+}
 
 /// Config as built by AgentBuilder and then static for the lifetime of the Agent.
-pub struct Agent {
-    pub user_agent: &'static str,
+pub(crate) struct AgentConfig {
+    /// `None` to send no `User-Agent`.
+    pub user_agent: Option<String>,
+    /// Resolves the paths given to [`Agent::url()`].
+    pub base_url: Option<Url>,
+    pub redirect_policy: RedirectPolicy,
+    pub resolver: Resolver,
+    /// Host and port connecting to a fixed address, see [`AgentBuilder::resolve()`].
+    pub resolve_overrides: Vec<(String, u16, SocketAddr)>,
+    /// Limit for establishing the TCP connection to each resolved address.
+    pub timeout_connect: Option<Duration>,
+    pub socket_options: SocketOptions,
+    /// Defaults for [`Request::timeout_read()`] and [`Request::timeout_write()`].
+    pub timeout_read: Option<Duration>,
+    pub timeout_write: Option<Duration>,
+    /// Default for [`Request::rate_limit()`].
+    pub rate_limit: Option<u64>,
+    /// Default for [`Request::retry()`], no retries unless set.
+    pub retry_policy: Option<RetryPolicy>,
+    pub proxy: Option<Proxy>,
+    /// Default for [`Request::auth_bearer()`].
+    pub bearer_token: Option<String>,
+    /// Run around every request, in order.
+    pub middleware: Vec<Arc<dyn Middleware>>,
+    /// Adds headers over the final request, see [`AgentBuilder::signer()`].
+    pub signer: Option<Arc<SignFn>>,
+    /// New credentials for a `401`, see [`AgentBuilder::on_unauthorized()`].
+    pub on_unauthorized: Option<Arc<UnauthorizedFn>>,
+    /// Limit for the status line and headers of a response.
+    pub max_response_header_size: usize,
+    /// Accept bare LF line ends, folded lines and sloppy status lines in
+    /// response heads.
+    pub lenient_headers: bool,
+    /// Gets the request and response heads, see [`AgentBuilder::wire_log()`].
+    pub wire_log: Option<WireLog>,
+    /// Hide credential headers in the `Debug` output of responses.
+    pub redact_debug: bool,
+    /// Stores responses to revalidate, see [`AgentBuilder::cache()`].
+    pub cache: Option<Arc<dyn Cache>>,
+    /// See [`AgentBuilder::stale_while_revalidate()`].
+    pub stale_while_revalidate: Option<Duration>,
+    /// Answers requests instead of the network.
+    #[cfg(feature = "testing")]
+    pub mock: Option<Mock>,
     #[cfg(feature = "tls")]
     pub tls_config: Arc<rustls::ClientConfig>,
 }
 
+/// State that changes as the agent makes requests, shared by its clones.
+pub(crate) struct AgentState {
+    pub pool: ConnectionPool,
+    #[cfg(feature = "cookies")]
+    pub cookie_store: Mutex<CookieStore>,
+    /// Urls of cached responses being revalidated in the background.
+    pub refreshing: Mutex<HashSet<String>>,
+}
+
+/// Agents keep configuration and state, like cookies, for the requests
+/// made through them.
+///
+/// An agent is created with an [`AgentBuilder`]. It can be cheaply cloned,
+/// all clones share the same configuration and state. The top level
+/// functions like [`get()`](crate::get) make requests through one process
+/// wide agent with the default configuration.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// let agent = ureq::AgentBuilder::new()
+///     .user_agent("my-crawler/1.0")
+///     .timeout_read(Some(Duration::from_secs(5)))
+///     .build();
+/// let url = ureq::Url::parse("http://example.com/")?;
+/// let resp = agent.get(url).call()?;
+/// # Ok::<(), ureq::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct Agent {
+    pub(crate) config: Arc<AgentConfig>,
+    pub(crate) state: Arc<AgentState>,
+}
+
+impl fmt::Debug for Agent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Agent")
+            .field("user_agent", &self.config.user_agent)
+            .field("redirect_policy", &self.config.redirect_policy)
+            .finish()
+    }
+}
+
 impl Agent {
+    /// Make a request with the given method from this agent.
+    ///
+    /// Any method can be sent, e.g. `PROPFIND` for WebDAV. Methods are
+    /// case sensitive, and one that isn't a token, RFC 7230 3.2.6, is an
+    /// [`ErrorKind::InvalidMethod`](crate::ErrorKind::InvalidMethod) error.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    /// let url = ureq::Url::parse("http://example.com/dav/")?;
+    /// let resp = agent.request("PROPFIND", url).set("Depth", "1").call()?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn request(&self, method: &str, u: Url) -> Request {
+        Request::new(self.clone(), method, u)
+    }
+
+    /// The cookies the agent keeps, e.g. to save them between runs.
+    ///
+    /// Requires the `cookies` feature.
+    #[cfg(feature = "cookies")]
+    pub fn cookie_store(&self) -> CookieStoreGuard<'_> {
+        CookieStoreGuard(self.state.cookie_store.lock().unwrap())
+    }
+
+    /// The url of `path` under the [base url](AgentBuilder::base_url()).
+    ///
+    /// A path starting with `/` is under the base url's path rather than
+    /// the root of the host. Other paths are resolved with [`Url::join()`],
+    /// so a full url is used as is. Without a base url, `path` must be a
+    /// full url.
+    ///
+    /// ```
+    /// let base = ureq::Url::parse("https://api.example.com/v2")?;
+    /// let agent = ureq::AgentBuilder::new().base_url(base).build();
+    ///
+    /// let url = agent.url("/users/42")?;
+    /// assert_eq!(url.serialization(), "https://api.example.com/v2/users/42");
+    /// let url = agent.url("https://other.example.com/")?;
+    /// assert_eq!(url.serialization(), "https://other.example.com/");
+    ///
+    /// let req = agent.get(agent.url("/users/42")?);
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn url(&self, path: &str) -> Result<Url, Error> {
+        let base = match &self.config.base_url {
+            Some(base) => base,
+            None => return Url::parse(path),
+        };
+        match path.strip_prefix('/') {
+            Some(rest) if !rest.starts_with('/') => {
+                let mut base = base.clone();
+                if !base.path().ends_with('/') {
+                    base.set_path(&format!("{}/", base.path()))?;
+                }
+                base.join(rest)
+            }
+            _ => base.join(path),
+        }
+    }
+
     /// Make a GET request from this agent.
-    pub fn get(u: &Url) -> Result<Response> {
-        Request::call(&USER_AGENT, u)
+    pub fn get(&self, u: Url) -> Request {
+        self.request("GET", u)
+    }
+
+    /// Make a POST request from this agent.
+    pub fn post(&self, u: Url) -> Request {
+        self.request("POST", u)
+    }
+
+    /// Make a PUT request from this agent.
+    pub fn put(&self, u: Url) -> Request {
+        self.request("PUT", u)
+    }
+
+    /// Make a DELETE request from this agent.
+    pub fn delete(&self, u: Url) -> Request {
+        self.request("DELETE", u)
+    }
+
+    /// Make a PATCH request from this agent.
+    pub fn patch(&self, u: Url) -> Request {
+        self.request("PATCH", u)
+    }
+
+    /// Make a HEAD request from this agent.
+    pub fn head(&self, u: Url) -> Request {
+        self.request("HEAD", u)
+    }
+
+    /// Make an OPTIONS request from this agent.
+    pub fn options(&self, u: Url) -> Request {
+        self.request("OPTIONS", u)
+    }
+
+    /// Download `url` to the file at `path`, continuing where an earlier
+    /// call left off if it was interrupted. Returns the size of the file.
+    ///
+    /// While a download is unfinished, the server's `ETag` or
+    /// `Last-Modified` is kept in `<path>.ureq-resume`. The next call asks
+    /// for the rest of the body with `Range` and `If-Range`, so that a
+    /// changed file is downloaded in full rather than spliced. Without
+    /// such a validator, or with a server that doesn't do ranges, the
+    /// download starts over.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    /// let url = ureq::Url::parse("http://example.com/big.iso")?;
+    /// let size = agent.download_resumable(url, "big.iso")?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn download_resumable(&self, url: Url, path: impl AsRef<Path>) -> Result<u64, Error> {
+        download::download_resumable(self, url, path.as_ref())
+    }
+
+    /// Open a connection to the server of `url` ahead of a request, so that
+    /// the request doesn't wait for DNS, connecting and the TLS handshake.
+    ///
+    /// The connection is put in the pool like one kept alive after a
+    /// response, and is subject to the same idle timeout and limits. Each
+    /// call opens a new connection, to warm up several for requests made
+    /// at once.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    /// let url = ureq::Url::parse("https://example.com/")?;
+    /// agent.preconnect(&url)?;
+    /// // later, on the connection opened above
+    /// let resp = agent.get(url).call()?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn preconnect(&self, url: &Url) -> Result<(), Error> {
+        #[cfg(feature = "testing")]
+        if self.config.mock.is_some() {
+            return Ok(());
+        }
+        unit::preconnect(&self.request("GET", url.clone()))
+    }
+
+    /// Close the idle connections in the pool, for a clean teardown.
+    ///
+    /// Connections still in use are closed rather than pooled once their
+    /// response is read, for this agent and all its clones. Requests can
+    /// still be made, each on a new connection.
+    pub fn shutdown(&self) {
+        self.state.pool.shutdown();
+    }
+
+    /// GET each of `urls`, several at a time on a few threads. The
+    /// results are in the order of `urls`, one failing doesn't stop the
+    /// others.
+    ///
+    /// Urls on the same host are sent one after the other over one or two
+    /// connections, kept alive in between. For that the bodies are read
+    /// into memory before the next request goes out.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    /// let urls = vec![
+    ///     ureq::Url::parse("http://example.com/a")?,
+    ///     ureq::Url::parse("http://example.com/b")?,
+    /// ];
+    /// for result in agent.send_multiple(urls) {
+    ///     match result {
+    ///         Ok(resp) => println!("{}", resp.into_string()?),
+    ///         Err(e) => println!("failed: {}", e),
+    ///     }
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn send_multiple(&self, urls: Vec<Url>) -> Vec<Result<Response, Error>> {
+        multiple::send_multiple(self, urls)
+    }
+}
+
+/// Builds an [`Agent`] with non-default configuration.
+pub struct AgentBuilder {
+    config: AgentConfig,
+    max_idle_connections: usize,
+    max_idle_connections_per_host: usize,
+    idle_timeout: Option<Duration>,
+    max_connection_age: Option<Duration>,
+    #[cfg(feature = "cookies")]
+    cookie_store: Option<CookieStore>,
+    #[cfg(feature = "tls")]
+    tls_config: Option<Arc<rustls::ClientConfig>>,
+    #[cfg(feature = "tls")]
+    root_certs: Vec<Certificate>,
+    #[cfg(feature = "tls")]
+    webpki_roots: bool,
+    #[cfg(feature = "tls")]
+    accept_invalid_certs: bool,
+    #[cfg(feature = "tls")]
+    cert_verifier: Option<Arc<dyn rustls::client::ServerCertVerifier>>,
+}
+
+impl AgentBuilder {
+    /// The default configuration: a `ureq/<version>` user agent, up to 5
+    /// redirects, a 30 second connect timeout, no read or write timeouts
+    /// and a pool of up to 100 idle connections, one per host, each kept for
+    /// up to 30 seconds.
+    pub fn new() -> Self {
+        AgentBuilder {
+            config: AgentConfig {
+                user_agent: Some(format!("ureq/{}", env!("CARGO_PKG_VERSION"))),
+                base_url: None,
+                redirect_policy: RedirectPolicy::Limited(5),
+                resolver: Resolver::System,
+                resolve_overrides: vec![],
+                timeout_connect: Some(Duration::from_secs(30)),
+                socket_options: SocketOptions {
+                    keepalive: None,
+                    nodelay: true,
+                    send_buffer_size: None,
+                    recv_buffer_size: None,
+                },
+                timeout_read: None,
+                rate_limit: None,
+                timeout_write: None,
+                retry_policy: None,
+                proxy: None,
+                bearer_token: None,
+                middleware: vec![],
+                signer: None,
+                on_unauthorized: None,
+                max_response_header_size: MAX_HEAD_SIZE,
+                lenient_headers: false,
+                wire_log: None,
+                redact_debug: true,
+                cache: None,
+                stale_while_revalidate: None,
+                #[cfg(feature = "testing")]
+                mock: None,
+                #[cfg(feature = "tls")]
+                tls_config: TLS_CONFIG.clone(),
+            },
+            max_idle_connections: 100,
+            max_idle_connections_per_host: 1,
+            idle_timeout: Some(Duration::from_secs(30)),
+            max_connection_age: None,
+            #[cfg(feature = "cookies")]
+            cookie_store: None,
+            #[cfg(feature = "tls")]
+            tls_config: None,
+            #[cfg(feature = "tls")]
+            root_certs: vec![],
+            #[cfg(feature = "tls")]
+            webpki_roots: true,
+            #[cfg(feature = "tls")]
+            accept_invalid_certs: false,
+            #[cfg(feature = "tls")]
+            cert_verifier: None,
+        }
+    }
+
+    /// Create the agent.
+    pub fn build(self) -> Agent {
+        #[allow(unused_mut)]
+        let mut config = self.config;
+        #[cfg(feature = "tls")]
+        {
+            config.tls_config = match (self.tls_config, self.cert_verifier) {
+                (Some(tls_config), _) => tls_config,
+                (None, _) if self.accept_invalid_certs => {
+                    verifier_client_config(Arc::new(NoVerifier))
+                }
+                (None, Some(verifier)) => verifier_client_config(verifier),
+                (None, None) if !self.root_certs.is_empty() || !self.webpki_roots => {
+                    client_config(&self.root_certs, self.webpki_roots)
+                }
+                (None, None) => TLS_CONFIG.clone(),
+            };
+            #[cfg(feature = "http2")]
+            {
+                config.tls_config = crate::h2::with_alpn(config.tls_config);
+            }
+        }
+        Agent {
+            config: Arc::new(config),
+            state: Arc::new(AgentState {
+                pool: ConnectionPool::new(
+                    self.max_idle_connections,
+                    self.max_idle_connections_per_host,
+                    self.idle_timeout,
+                    self.max_connection_age,
+                ),
+                #[cfg(feature = "cookies")]
+                cookie_store: Mutex::new(self.cookie_store.unwrap_or_default()),
+                refreshing: Mutex::new(HashSet::new()),
+            }),
+        }
+    }
+
+    /// The `User-Agent` header sent with every request, `ureq/<version>`
+    /// unless changed.
+    ///
+    /// ```
+    /// let version = "1.4.2";
+    /// let agent = ureq::AgentBuilder::new()
+    ///     .user_agent(format!("myapp/{} (+https://example.com/bot)", version))
+    ///     .build();
+    /// ```
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.config.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Send no `User-Agent` header, unless set on a request.
+    pub fn no_user_agent(mut self) -> Self {
+        self.config.user_agent = None;
+        self
+    }
+
+    /// Resolve the paths given to [`Agent::url()`] under `url`, e.g.
+    /// `https://api.example.com/v2`.
+    pub fn base_url(mut self, url: Url) -> Self {
+        self.config.base_url = Some(url);
+        self
+    }
+
+    /// Follow up to `n` redirects, see [`Request::redirects()`].
+    pub fn redirects(self, n: u32) -> Self {
+        let policy = match n {
+            0 => RedirectPolicy::None,
+            n => RedirectPolicy::Limited(n),
+        };
+        self.redirect_policy(policy)
+    }
+
+    /// Decide which redirects to follow, see [`RedirectPolicy`].
+    pub fn redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.config.redirect_policy = policy;
+        self
+    }
+
+    /// How host names are resolved, [`Resolver::System`] by default.
+    pub fn resolver(mut self, resolver: Resolver) -> Self {
+        self.config.resolver = resolver;
+        self
+    }
+
+    /// Connect to `addr` for requests to `host` and `port`, without
+    /// resolving the host, like curl's `--resolve`. The host is still used
+    /// for the `Host` header and to check the server's certificate, e.g.
+    /// to point a production name at a staging server.
+    ///
+    /// A later override for the same host and port replaces the earlier.
+    /// [`Request::connect_to()`] takes precedence.
+    ///
+    /// ```no_run
+    /// let agent = ureq::AgentBuilder::new()
+    ///     .resolve("api.example.com", 443, "10.0.0.5:8443".parse().unwrap())
+    ///     .build();
+    /// ```
+    pub fn resolve(mut self, host: &str, port: u16, addr: SocketAddr) -> Self {
+        let overrides = &mut self.config.resolve_overrides;
+        overrides.retain(|(h, p, _)| !(h.eq_ignore_ascii_case(host) && *p == port));
+        overrides.push((host.to_string(), port, addr));
+        self
+    }
+
+    /// Limit for establishing the TCP connection, tried for each address
+    /// the host resolves to. `None` leaves it to the OS.
+    ///
+    /// Default for [`Request::timeout_connect()`].
+    pub fn timeout_connect(mut self, timeout: Option<Duration>) -> Self {
+        self.config.timeout_connect = timeout;
+        self
+    }
+
+    /// Send TCP keepalive probes after a connection is idle for `idle`,
+    /// and then every `idle` until answered, so that pooled connections
+    /// aren't dropped by NATs and firewalls. Off by default.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// let agent = ureq::AgentBuilder::new()
+    ///     .tcp_keepalive(Some(Duration::from_secs(60)))
+    ///     .build();
+    /// ```
+    pub fn tcp_keepalive(mut self, idle: Option<Duration>) -> Self {
+        self.config.socket_options.keepalive = idle;
+        self
+    }
+
+    /// Set `TCP_NODELAY`, sending small writes without waiting to
+    /// combine them. On by default.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.config.socket_options.nodelay = nodelay;
+        self
+    }
+
+    /// The size of the socket send buffer, `None` leaves it to the OS.
+    pub fn send_buffer_size(mut self, size: Option<usize>) -> Self {
+        self.config.socket_options.send_buffer_size = size;
+        self
+    }
+
+    /// The size of the socket receive buffer, `None` leaves it to the OS.
+    pub fn recv_buffer_size(mut self, size: Option<usize>) -> Self {
+        self.config.socket_options.recv_buffer_size = size;
+        self
+    }
+
+    /// Default for [`Request::timeout_read()`].
+    pub fn timeout_read(mut self, timeout: Option<Duration>) -> Self {
+        self.config.timeout_read = timeout;
+        self
+    }
+
+    /// Default for [`Request::timeout_write()`].
+    pub fn timeout_write(mut self, timeout: Option<Duration>) -> Self {
+        self.config.timeout_write = timeout;
+        self
+    }
+
+    /// Default for [`Request::rate_limit()`]. Each request is limited on
+    /// its own, not the agent's requests together.
+    pub fn rate_limit(mut self, rate: Option<u64>) -> Self {
+        self.config.rate_limit = rate;
+        self
+    }
+
+    /// Limit for the status line and headers of a response, 64 KiB by
+    /// default. Larger ones are an [`ErrorKind::BadHeader`](crate::ErrorKind::BadHeader)
+    /// error, so a misbehaving server can't make a request buffer
+    /// unbounded amounts of data.
+    pub fn max_response_header_size(mut self, max: usize) -> Self {
+        self.config.max_response_header_size = max;
+        self
+    }
+
+    /// Default for [`Request::auth_bearer()`], sent with every request of
+    /// the agent unless the request sets its own `Authorization`.
+    ///
+    /// Every request means to any host, so an agent with a token is best
+    /// kept for the API the token is for.
+    pub fn auth_bearer(mut self, token: &str) -> Self {
+        self.config.bearer_token = Some(token.to_string());
+        self
+    }
+
+    /// Add middleware run around every request of the agent, after the
+    /// middleware added before. See [`Middleware`].
+    pub fn middleware(mut self, middleware: impl Middleware) -> Self {
+        self.config.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Sign every request sent by the agent, e.g. with AWS SigV4 or an
+    /// HMAC over the body.
+    ///
+    /// The closure runs right before sending, once per attempt, so retries
+    /// and redirects are signed again. It gets the method, url, the final
+    /// headers and the body, and returns headers to add. These replace
+    /// any headers already set with the same name.
+    ///
+    /// ```no_run
+    /// let agent = ureq::AgentBuilder::new()
+    ///     .signer(|req: &ureq::SignRequest| {
+    ///         let body = req.body().unwrap_or_default();
+    ///         let sum: u32 = body.iter().map(|b| *b as u32).sum();
+    ///         vec![("X-Checksum".to_string(), sum.to_string())]
+    ///     })
+    ///     .build();
+    /// let url = ureq::Url::parse("http://example.com/hook")?;
+    /// agent.post(url).send_string("hello")?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn signer(
+        mut self,
+        signer: impl Fn(&SignRequest) -> Vec<(String, String)> + Send + Sync + 'static,
+    ) -> Self {
+        self.config.signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Get new credentials when a request is answered with `401
+    /// Unauthorized`, e.g. by refreshing an OAuth access token.
+    ///
+    /// The closure gets the `401` response. When it returns a value, the
+    /// request is sent once more with that as the `Authorization` header.
+    /// `None`, a second `401`, or a body sent from a reader, which can't
+    /// be sent again, make the `401` the response.
+    ///
+    /// ```no_run
+    /// let agent = ureq::AgentBuilder::new()
+    ///     .on_unauthorized(|_resp: &ureq::Response| {
+    ///         let token = "fresh-token"; // e.g. from a token endpoint
+    ///         Some(format!("Bearer {}", token))
+    ///     })
+    ///     .build();
+    /// let url = ureq::Url::parse("http://example.com/me")?;
+    /// let resp = agent.get(url).call()?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn on_unauthorized(
+        mut self,
+        refresh: impl Fn(&Response) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.config.on_unauthorized = Some(Arc::new(refresh));
+        self
+    }
+
+    /// Accept response heads from legacy servers that end lines with a bare
+    /// LF rather than CRLF, or continue a header on the next line with
+    /// leading whitespace, the obsolete line folding.
+    ///
+    /// Status lines with the spacing off, as sent by some embedded servers,
+    /// are accepted too: `HTTP/1.1 200OK`, `HTTP/1.1  200 OK` and
+    /// `HTTP/1.1 200` without a reason phrase.
+    ///
+    /// Off by default, when such heads are an
+    /// [`ErrorKind::BadHeader`](crate::ErrorKind::BadHeader) or
+    /// [`ErrorKind::BadStatus`](crate::ErrorKind::BadStatus) error.
+    pub fn lenient_headers(mut self, lenient: bool) -> Self {
+        self.config.lenient_headers = lenient;
+        self
+    }
+
+    /// Log the request and response heads as written and read, like
+    /// `curl -v`, to troubleshoot what goes over the wire.
+    ///
+    /// The values of `Authorization`, `Proxy-Authorization`, `Cookie` and
+    /// `Set-Cookie` headers are replaced by `[redacted]`, see
+    /// [`wire_log_unredacted()`](AgentBuilder::wire_log_unredacted) to
+    /// keep them.
+    ///
+    /// ```no_run
+    /// use ureq::Wire;
+    ///
+    /// let agent = ureq::AgentBuilder::new()
+    ///     .wire_log(|wire: Wire| match wire {
+    ///         Wire::Request(head) => eprint!("> {}", head),
+    ///         Wire::Response(head) => eprint!("< {}", head),
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn wire_log(mut self, log: impl Fn(Wire) + Send + Sync + 'static) -> Self {
+        self.config.wire_log = Some(WireLog {
+            log: Arc::new(log),
+            redact: true,
+        });
+        self
+    }
+
+    /// Keep responses in `cache` and revalidate them rather than download
+    /// them again, see [`cache`](crate::cache).
+    pub fn cache(mut self, cache: impl Cache + 'static) -> Self {
+        self.config.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// For how long past its max age a cached response is still returned,
+    /// while it's revalidated on a background thread. For responses
+    /// without a `Cache-Control: stale-while-revalidate` of their own.
+    /// `None`, the default, revalidates before returning.
+    pub fn stale_while_revalidate(mut self, stale: Option<Duration>) -> Self {
+        self.config.stale_while_revalidate = stale;
+        self
+    }
+
+    /// Answer the agent's requests from `mock` rather than the network,
+    /// see [`testing`](crate::testing).
+    #[cfg(feature = "testing")]
+    pub fn mock(mut self, mock: Mock) -> Self {
+        self.config.mock = Some(mock);
+        self
+    }
+
+    /// Whether the `Debug` output of a [`Response`] replaces the values of
+    /// headers with credentials or cookies, like `Set-Cookie`, with
+    /// `[redacted]`. On by default, so that logging a response with `{:?}`
+    /// doesn't leak a session.
+    pub fn redact_debug(mut self, redact: bool) -> Self {
+        self.config.redact_debug = redact;
+        self
+    }
+
+    /// Like [`wire_log()`](AgentBuilder::wire_log), but with credentials
+    /// and cookies in the clear.
+    pub fn wire_log_unredacted(mut self, log: impl Fn(Wire) + Send + Sync + 'static) -> Self {
+        self.config.wire_log = Some(WireLog {
+            log: Arc::new(log),
+            redact: false,
+        });
+        self
+    }
+
+    /// How many idle connections the agent keeps for reuse in total.
+    /// `0` disables the pool.
+    pub fn max_idle_connections(mut self, max: usize) -> Self {
+        self.max_idle_connections = max;
+        self
+    }
+
+    /// How many idle connections the agent keeps for reuse per scheme,
+    /// host and port. `0` disables the pool.
+    pub fn max_idle_connections_per_host(mut self, max: usize) -> Self {
+        self.max_idle_connections_per_host = max;
+        self
+    }
+
+    /// Close pooled connections that were idle for longer than this rather
+    /// than reusing them, since servers close idle keep-alive connections
+    /// after a while. `None` keeps them until the pool is full.
+    ///
+    /// A shorter timeout the server gives in a `Keep-Alive: timeout=5`
+    /// response header wins, and a connection it takes no more requests
+    /// on, `Keep-Alive: max=0`, isn't kept.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Close pooled connections established longer ago than this, however
+    /// recently they were used. No limit by default.
+    pub fn max_connection_age(mut self, age: Option<Duration>) -> Self {
+        self.max_connection_age = age;
+        self
+    }
+
+    /// Retry failed requests, see [`RetryPolicy`].
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.config.retry_policy = Some(policy);
+        self
+    }
+
+    /// Tunnel all requests through an HTTP proxy, see [`Proxy`].
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.config.proxy = Some(proxy);
+        self
+    }
+
+    /// The TLS configuration for https, by default trusting the
+    /// [webpki-roots](https://docs.rs/webpki-roots) certificates, or those
+    /// of the OS with the `native-roots` feature.
+    ///
+    /// Root certificates given with [`root_certificate()`](AgentBuilder::root_certificate)
+    /// and [`webpki_roots()`](AgentBuilder::webpki_roots) don't apply to it.
+    ///
+    /// Requires the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub fn tls_config(mut self, tls_config: Arc<rustls::ClientConfig>) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    /// Trust `cert` for https as well, see [`Certificate`].
+    ///
+    /// Requires the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub fn root_certificate(mut self, cert: Certificate) -> Self {
+        self.root_certs.push(cert);
+        self
+    }
+
+    /// Whether to trust the bundled webpki-roots certificates, or those of
+    /// the OS with the `native-roots` feature, `true` by default. With
+    /// `false` only the [root certificates](AgentBuilder::root_certificate)
+    /// given are trusted.
+    ///
+    /// Requires the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub fn webpki_roots(mut self, enabled: bool) -> Self {
+        self.webpki_roots = enabled;
+        self
+    }
+
+    /// Accept any certificate from https servers, expired, self-signed or
+    /// for another host, e.g. to develop against a local service.
+    ///
+    /// **This is insecure**, anyone able to intercept the connection can
+    /// read and change it. Prefer trusting the service's certificate with
+    /// [`root_certificate()`](AgentBuilder::root_certificate). Has no effect
+    /// together with [`tls_config()`](AgentBuilder::tls_config).
+    ///
+    /// Requires the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Leave checking server certificates to `verifier`, e.g. to pin a
+    /// certificate or require certificate transparency, keeping the rest
+    /// of the default TLS configuration.
+    ///
+    /// Root certificates given with [`root_certificate()`](AgentBuilder::root_certificate)
+    /// and [`webpki_roots()`](AgentBuilder::webpki_roots) are not used, the
+    /// verifier decides alone. For control over all of the TLS configuration
+    /// see [`tls_config()`](AgentBuilder::tls_config), which wins over this.
+    ///
+    /// Requires the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub fn certificate_verifier(
+        mut self,
+        verifier: Arc<dyn rustls::client::ServerCertVerifier>,
+    ) -> Self {
+        self.cert_verifier = Some(verifier);
+        self
+    }
+
+    /// Start with these cookies rather than an empty store, e.g. ones
+    /// [loaded](CookieStore::load) from disk.
+    ///
+    /// Requires the `cookies` feature.
+    #[cfg(feature = "cookies")]
+    pub fn cookie_store(mut self, cookie_store: CookieStore) -> Self {
+        self.cookie_store = Some(cookie_store);
+        self
     }
 }
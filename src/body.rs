@@ -1 +1,146 @@
+use std::io::{self, Read, Write};
 
+/// The different kinds of bodies to send.
+///
+/// *Internal API*
+pub(crate) enum Payload<'a> {
+    Empty,
+    Text(&'a str),
+    Bytes(&'a [u8]),
+    Reader(Box<dyn Read + 'a>),
+}
+
+/// The size of the body.
+///
+/// *Internal API*
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum BodySize {
+    Empty,
+    Unknown,
+    Known(u64),
+}
+
+/// Payloads are turned into this type where we can hold both a size and the reader.
+///
+/// *Internal API*
+pub(crate) struct SizedReader<'a> {
+    pub size: BodySize,
+    pub reader: Box<dyn Read + 'a>,
+    /// The whole body, unless it's streamed from a reader.
+    pub bytes: Option<&'a [u8]>,
+}
+
+impl<'a> SizedReader<'a> {
+    fn new(size: BodySize, reader: Box<dyn Read + 'a>) -> Self {
+        SizedReader {
+            size,
+            reader,
+            bytes: None,
+        }
+    }
+
+    fn from_bytes(bytes: &'a [u8]) -> Self {
+        SizedReader {
+            size: BodySize::Known(bytes.len() as u64),
+            reader: Box::new(bytes),
+            bytes: Some(bytes),
+        }
+    }
+}
+
+impl<'a> Payload<'a> {
+    /// A copy to send again, unless it is a reader that can only be read once.
+    pub fn try_clone(&self) -> Option<Payload<'a>> {
+        match self {
+            Payload::Empty => Some(Payload::Empty),
+            Payload::Text(text) => Some(Payload::Text(text)),
+            Payload::Bytes(bytes) => Some(Payload::Bytes(bytes)),
+            Payload::Reader(_) => None,
+        }
+    }
+
+    pub fn into_read(self) -> SizedReader<'a> {
+        match self {
+            Payload::Empty => SizedReader {
+                size: BodySize::Empty,
+                reader: Box::new(io::empty()),
+                bytes: Some(&[]),
+            },
+            Payload::Text(text) => SizedReader::from_bytes(text.as_bytes()),
+            Payload::Bytes(bytes) => SizedReader::from_bytes(bytes),
+            Payload::Reader(read) => SizedReader::new(BodySize::Unknown, read),
+        }
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl<'a> Payload<'a> {
+    /// The payload compressed with gzip. In memory for a payload that is,
+    /// using `buf`, to keep the size known.
+    pub fn gzip(self, buf: &'a mut Vec<u8>) -> io::Result<Payload<'a>> {
+        use flate2::{read::GzEncoder, write::GzEncoder as GzWriter, Compression};
+
+        let bytes = match self {
+            Payload::Empty => return Ok(Payload::Empty),
+            Payload::Reader(read) => {
+                let read = GzEncoder::new(read, Compression::default());
+                return Ok(Payload::Reader(Box::new(read)));
+            }
+            Payload::Text(text) => text.as_bytes(),
+            Payload::Bytes(bytes) => bytes,
+        };
+        let mut writer = GzWriter::new(buf, Compression::default());
+        writer.write_all(bytes)?;
+        Ok(Payload::Bytes(writer.finish()?))
+    }
+}
+
+const CHUNK_MAX_SIZE: usize = 0x4000; // Maximum size of a TLS fragment
+const CHUNK_HEADER_MAX_SIZE: usize = 6; // four hex digits plus "\r\n"
+const CHUNK_FOOTER_SIZE: usize = 2; // "\r\n"
+const CHUNK_MAX_PAYLOAD_SIZE: usize = CHUNK_MAX_SIZE - CHUNK_HEADER_MAX_SIZE - CHUNK_FOOTER_SIZE;
+
+// copy_chunked() improves on chunked_transfer's Encoder + io::copy with the
+// following performance optimizations:
+// 1) It avoid copying memory.
+// 2) chunked_transfer's Encoder issues 4 separate write() per chunk. This is costly
+//    overhead. Instead, we do a single write() per chunk.
+// The buffer has room for the chunk header in front of the payload, which is
+// filled in once the payload size is known, and room for the footer after it.
+fn copy_chunked<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<u64> {
+    let mut chunk = vec![0; CHUNK_MAX_SIZE];
+    let mut written = 0;
+    loop {
+        let n = loop {
+            let payload = &mut chunk[CHUNK_HEADER_MAX_SIZE..][..CHUNK_MAX_PAYLOAD_SIZE];
+            match reader.read(payload) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                v => break v?,
+            }
+        };
+
+        let header = format!("{:x}\r\n", n);
+        let start = CHUNK_HEADER_MAX_SIZE - header.len();
+        chunk[start..CHUNK_HEADER_MAX_SIZE].copy_from_slice(header.as_bytes());
+        let end = CHUNK_HEADER_MAX_SIZE + n;
+        chunk[end..end + CHUNK_FOOTER_SIZE].copy_from_slice(b"\r\n");
+
+        writer.write_all(&chunk[start..end + CHUNK_FOOTER_SIZE])?;
+        written += n as u64;
+
+        // A zero sized chunk is the terminating one.
+        if n == 0 {
+            return Ok(written);
+        }
+    }
+}
+
+/// Helper to send a body, either as chunked or not.
+pub(crate) fn send_body(mut body: SizedReader, stream: &mut impl Write) -> io::Result<()> {
+    if let BodySize::Unknown = body.size {
+        copy_chunked(&mut body.reader, stream)?;
+    } else {
+        io::copy(&mut body.reader, stream)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,437 @@
+//! Caching of responses, following `Cache-Control` and revalidating them
+//! with the server.
+//!
+//! An agent given a [`Cache`] with
+//! [`AgentBuilder::cache()`](crate::AgentBuilder::cache) stores the `200 OK`
+//! responses to `GET` requests that have an `ETag` or `Last-Modified`
+//! header, or a `Cache-Control: max-age`. Requesting the same url again
+//! within the max age is answered from the cache. Otherwise the request
+//! is sent with `If-None-Match` or `If-Modified-Since`, and when the server
+//! answers `304 Not Modified` the stored response is returned in its place,
+//! with the body not sent again.
+//!
+//! Of `Cache-Control`, responses with `no-store` aren't stored, those with
+//! `no-cache` are revalidated each time, and those with `private` aren't
+//! stored by a [shared](Cache::is_shared) cache. A request's own
+//! `Cache-Control: no-cache` or `no-store` revalidates or skips the cache.
+//! Within `stale-while-revalidate`, or
+//! [`AgentBuilder::stale_while_revalidate()`](crate::AgentBuilder::stale_while_revalidate),
+//! past the max age, the stored response is returned right away and
+//! revalidated on a background thread. `Expires` and `Vary` are not
+//! looked at.
+//!
+//! Responses are keyed by url without the user name and password. A
+//! shared cache only stores the response to a request with credentials,
+//! in the url or an `Authorization` header, if the response allows it
+//! with `public`, `s-maxage` or `must-revalidate`, see RFC 9111 3.5.
+//!
+//! ```no_run
+//! use ureq::cache::MemoryCache;
+//!
+//! let agent = ureq::AgentBuilder::new().cache(MemoryCache::new()).build();
+//! let url = ureq::Url::parse("http://example.com/feed.xml")?;
+//! let first = agent.get(url.clone()).call()?.into_string()?;
+//! // a 304 from the server, same body
+//! let second = agent.get(url).call()?.into_string()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Requests with their own `If-None-Match`, `If-Modified-Since` or `Range`
+//! headers go around the cache.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::body::Payload;
+use crate::error::Error;
+use crate::request::Request;
+use crate::response::Response;
+use crate::timings::Timings;
+
+/// Storage for cached responses, keyed by url.
+///
+/// [`MemoryCache`] and [`DiskCache`] are provided, implement this to keep
+/// responses elsewhere. Storage is best effort, failures aren't reported.
+pub trait Cache: Send + Sync {
+    fn get(&self, url: &str) -> Option<CachedResponse>;
+    fn put(&self, url: &str, response: &CachedResponse);
+    fn remove(&self, url: &str);
+
+    /// Whether the cache serves several users, like a cache in a server
+    /// making requests on behalf of its users. A shared cache doesn't store
+    /// `Cache-Control: private` responses, and goes by `s-maxage`.
+    fn is_shared(&self) -> bool {
+        false
+    }
+}
+
+/// A response as stored in a [`Cache`], with its body.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: u16,
+    /// The headers, with a `Content-Length` rather than a
+    /// `Transfer-Encoding`.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// When the response was received or last revalidated, which its max
+    /// age counts from.
+    pub stored: SystemTime,
+}
+
+impl CachedResponse {
+    /// The value of a header, names are case insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Keeps responses in memory, for the lifetime of the agent.
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<String, CachedResponse>>,
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        MemoryCache::default()
+    }
+}
+
+impl Cache for MemoryCache {
+    fn get(&self, url: &str) -> Option<CachedResponse> {
+        self.entries.lock().unwrap().get(url).cloned()
+    }
+
+    fn put(&self, url: &str, response: &CachedResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(url.to_string(), response.clone());
+    }
+
+    fn remove(&self, url: &str) {
+        self.entries.lock().unwrap().remove(url);
+    }
+}
+
+/// Keeps responses as files in a directory, one per url, so they last
+/// between runs.
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    /// Store the responses in `dir`, which is created if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(DiskCache { dir })
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.cache", fnv1a(url.as_bytes())))
+    }
+}
+
+// The file holds the url, to tell apart urls with the same hash, then the
+// status, the time stored in seconds since the epoch, the headers and the
+// body:
+//
+// http://example.com/\r\n
+// 200\r\n
+// 1445412480\r\n
+// ETag: "abc"\r\n
+// \r\n
+// <body>
+impl Cache for DiskCache {
+    fn get(&self, url: &str) -> Option<CachedResponse> {
+        let mut data = vec![];
+        fs::File::open(self.path(url))
+            .ok()?
+            .read_to_end(&mut data)
+            .ok()?;
+        let end = data.windows(4).position(|w| w == b"\r\n\r\n")?;
+        let head = std::str::from_utf8(&data[..end]).ok()?;
+        let mut lines = head.split("\r\n");
+        if lines.next()? != url {
+            return None;
+        }
+        let status = lines.next()?.parse().ok()?;
+        let stored = UNIX_EPOCH.checked_add(Duration::from_secs(lines.next()?.parse().ok()?))?;
+        let headers = lines
+            .map(|line| {
+                let (name, value) = line.split_once(':')?;
+                Some((name.to_string(), value.trim().to_string()))
+            })
+            .collect::<Option<_>>()?;
+        Some(CachedResponse {
+            status,
+            headers,
+            body: data[end + 4..].to_vec(),
+            stored,
+        })
+    }
+
+    fn put(&self, url: &str, response: &CachedResponse) {
+        let stored = response
+            .stored
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut data = format!("{}\r\n{}\r\n{}\r\n", url, response.status, stored.as_secs());
+        for (name, value) in &response.headers {
+            data.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        data.push_str("\r\n");
+        let mut data = data.into_bytes();
+        data.extend_from_slice(&response.body);
+        // written aside and renamed, so a reader never sees half a file
+        let path = self.path(url);
+        let tmp = path.with_extension("tmp");
+        if fs::write(&tmp, data).is_ok() {
+            let _ = fs::rename(&tmp, &path);
+        }
+    }
+
+    fn remove(&self, url: &str) {
+        let _ = fs::remove_file(self.path(url));
+    }
+}
+
+// FNV-1a, for file names that stay the same between Rust versions.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+const CONDITIONAL: &[&str] = &["If-None-Match", "If-Modified-Since"];
+
+/// The `Cache-Control` directives the cache follows.
+#[derive(Default)]
+struct CacheControl {
+    max_age: Option<u64>,
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    // any of public, s-maxage and must-revalidate, which let a shared
+    // cache store the response to a request with credentials
+    shareable: bool,
+    stale_while_revalidate: Option<u64>,
+}
+
+impl CacheControl {
+    /// From the values of all `Cache-Control` headers. A shared cache
+    /// goes by `s-maxage` over `max-age`.
+    fn parse<'a>(values: impl Iterator<Item = &'a str>, shared: bool) -> Self {
+        let mut cc = CacheControl::default();
+        let mut s_maxage = None;
+        for directive in values.flat_map(|v| v.split(',')) {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let secs = value.and_then(delta_seconds);
+            match name.to_ascii_lowercase().as_str() {
+                "max-age" => cc.max_age = cc.max_age.or(secs),
+                "s-maxage" => {
+                    s_maxage = s_maxage.or(secs);
+                    cc.shareable = true;
+                }
+                "public" | "must-revalidate" => cc.shareable = true,
+                "no-store" => cc.no_store = true,
+                "no-cache" => cc.no_cache = true,
+                "private" => cc.private = true,
+                "stale-while-revalidate" => cc.stale_while_revalidate = secs,
+                _ => {}
+            }
+        }
+        if shared && s_maxage.is_some() {
+            cc.max_age = s_maxage;
+        }
+        cc
+    }
+
+    fn of(cached: &CachedResponse, shared: bool) -> Self {
+        let values = cached
+            .headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case("cache-control"))
+            .map(|(_, v)| v.as_str());
+        CacheControl::parse(values, shared)
+    }
+}
+
+// The largest delta-seconds, RFC 9111 §1.2.2 has larger ones taken as it.
+const MAX_DELTA_SECONDS: u64 = 1 << 31;
+
+fn delta_seconds(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secs = value.parse().unwrap_or(MAX_DELTA_SECONDS);
+    Some(secs.min(MAX_DELTA_SECONDS))
+}
+
+/// Send `req`, a GET, with `send`, unless `cache` has a fresh response
+/// for it. A stale one is revalidated.
+pub(crate) fn send(
+    req: &mut Request,
+    cache: &Arc<dyn Cache>,
+    send: impl FnOnce(&Request) -> Result<Response, Error>,
+) -> Result<Response, Error> {
+    let asked = CacheControl::parse(req.header("Cache-Control").into_iter(), false);
+    let own = CONDITIONAL
+        .iter()
+        .chain(&["Range"])
+        .any(|h| req.header(h).is_some());
+    if own || asked.no_store {
+        return send(req);
+    }
+    let key = req.url.without_userinfo().to_string();
+    let cached = cache.get(&key);
+
+    if let Some(cached) = &cached {
+        let cc = CacheControl::of(cached, cache.is_shared());
+        let age = cached.stored.elapsed().unwrap_or_default();
+        let max_age = cc.max_age.map(Duration::from_secs);
+        let stale_for = cc
+            .stale_while_revalidate
+            .map(Duration::from_secs)
+            .or(req.agent.config.stale_while_revalidate);
+        match (max_age, stale_for) {
+            _ if asked.no_cache || cc.no_cache => {}
+            (Some(max_age), _) if age < max_age => {
+                return Response::from_cached(cached, Timings::new());
+            }
+            (Some(max_age), Some(stale_for)) if age < max_age.saturating_add(stale_for) => {
+                refresh_in_background(req, cache, &key, cached);
+                return Response::from_cached(cached, Timings::new());
+            }
+            _ => {}
+        }
+    }
+    revalidate(req, cache.as_ref(), &key, cached, send)
+}
+
+// Revalidate a stale response on another thread, unless that is already
+// being done. Middleware doesn't see the request.
+fn refresh_in_background(
+    req: &Request,
+    cache: &Arc<dyn Cache>,
+    key: &str,
+    cached: &CachedResponse,
+) {
+    let agent = req.agent.clone();
+    let started = agent
+        .state
+        .refreshing
+        .lock()
+        .unwrap()
+        .insert(key.to_string());
+    if !started {
+        return;
+    }
+    let mut bg = agent.request("GET", req.url.clone());
+    bg.headers = req.headers.clone();
+    bg.timeout_read = req.timeout_read;
+    bg.timeout_write = req.timeout_write;
+    let (cache, key, cached) = (cache.clone(), key.to_string(), cached.clone());
+    thread::spawn(move || {
+        let send = |req: &Request| req.send_retrying(Payload::Empty, None);
+        let _ = revalidate(&mut bg, cache.as_ref(), &key, Some(cached), send);
+        agent.state.refreshing.lock().unwrap().remove(&key);
+    });
+}
+
+// Send `req` conditional on the validators of `cached`, and store the
+// response if it may be.
+fn revalidate(
+    req: &mut Request,
+    cache: &dyn Cache,
+    key: &str,
+    cached: Option<CachedResponse>,
+    send: impl FnOnce(&Request) -> Result<Response, Error>,
+) -> Result<Response, Error> {
+    let mut validators = vec![];
+    if let Some(cached) = &cached {
+        if let Some(etag) = cached.header("etag") {
+            validators.push((CONDITIONAL[0].to_string(), etag.to_string()));
+        }
+        if let Some(modified) = cached.header("last-modified") {
+            validators.push((CONDITIONAL[1].to_string(), modified.to_string()));
+        }
+    }
+    // only for this request, not the redirects that may follow
+    let added = validators.len();
+    req.headers.extend(validators);
+    let result = send(req);
+    let len = req.headers.len();
+    req.headers.truncate(len - added);
+    let resp = result?;
+
+    let cc = CacheControl::parse(resp.header_all("cache-control"), cache.is_shared());
+    let authorized = req.header("Authorization").is_some()
+        || !req.url.username().is_empty()
+        || req.url.password().is_some();
+    let shared = cache.is_shared();
+    let storable = !(cc.no_store || cc.private && shared || authorized && shared && !cc.shareable);
+    let validated = resp.header("etag").is_some() || resp.header("last-modified").is_some();
+    match (resp.status_code(), cached) {
+        (304, Some(mut cached)) => {
+            // a 304 may update the stored headers, but not the body's
+            for (name, value) in resp.headers() {
+                if is_body_header(name) {
+                    continue;
+                }
+                cached
+                    .headers
+                    .retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+                cached.headers.push((name.to_string(), value.to_string()));
+            }
+            cached.stored = SystemTime::now();
+            let timings = *resp.timings();
+            io::copy(&mut resp.into_reader(), &mut io::sink())?;
+            match storable {
+                true => cache.put(key, &cached),
+                false => cache.remove(key),
+            }
+            Response::from_cached(&cached, timings)
+        }
+        (200, _) if storable && (validated || cc.max_age.is_some()) => {
+            let resp = resp.into_buffered()?;
+            let timings = *resp.timings();
+            let headers = resp
+                .headers()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect();
+            let mut body = vec![];
+            Read::read_to_end(&mut resp.into_reader(), &mut body)?;
+            let cached = CachedResponse {
+                status: 200,
+                headers,
+                body,
+                stored: SystemTime::now(),
+            };
+            cache.put(key, &cached);
+            Response::from_cached(&cached, timings)
+        }
+        (200, Some(_)) => {
+            cache.remove(key);
+            Ok(resp)
+        }
+        _ => Ok(resp),
+    }
+}
+
+fn is_body_header(name: &str) -> bool {
+    ["content-length", "transfer-encoding", "content-encoding"]
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,63 @@
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::error::{Error, ErrorKind};
+
+/// Aborts a request from another thread, see
+/// [`Request::cancel_token()`](crate::Request::cancel_token).
+///
+/// Cancelling shuts down the connection the request is on, so a request
+/// blocked sending or waiting for the response fails right away with
+/// [`ErrorKind::Cancelled`], and reading the body with an io error,
+/// [`io::ErrorKind::ConnectionAborted`](std::io::ErrorKind::ConnectionAborted)
+/// over HTTP/1.1. A request not yet connected fails once it is.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    // a handle to the socket of the request while it's in flight
+    socket: Mutex<Option<TcpStream>>,
+}
+
+impl CancelToken {
+    pub(crate) fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// Cancel the request. Once its body has been read, this does nothing.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        if let Some(socket) = self.inner.socket.lock().unwrap().take() {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        match self.is_cancelled() {
+            true => Err(ErrorKind::Cancelled.msg("Request was cancelled")),
+            false => Ok(()),
+        }
+    }
+
+    /// Shut down `socket` if the request is cancelled while on it.
+    pub(crate) fn watch(&self, socket: &TcpStream) -> Result<(), Error> {
+        let mut slot = self.inner.socket.lock().unwrap();
+        self.check()?;
+        *slot = socket.try_clone().ok();
+        Ok(())
+    }
+
+    /// The request is done with its socket, e.g. it went back to the pool.
+    pub(crate) fn release(&self) {
+        self.inner.socket.lock().unwrap().take();
+    }
+}
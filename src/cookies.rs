@@ -0,0 +1,358 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::MutexGuard;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::date::parse_http_date;
+use crate::url::{Scheme, Url};
+
+/// A cookie as stored by the agent, see RFC 6265 5.3.
+#[derive(Debug, Clone)]
+struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    // without a Domain attribute the cookie only goes back to the exact host
+    host_only: bool,
+    path: String,
+    secure: bool,
+    // not for scripts, kept to save it as such
+    http_only: bool,
+    expires: Option<SystemTime>,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.map(|e| e <= now).unwrap_or(false)
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().to_ascii_lowercase();
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(&host, &self.domain)
+        };
+        domain_ok && path_match(url.path(), &self.path) && (!self.secure || is_secure(url))
+    }
+}
+
+/// The cookies an agent received, sent back on later requests.
+///
+/// Cookies are matched on domain, path and the `Secure` attribute, and
+/// dropped once expired. There is no public suffix list, so only a
+/// `Domain` without a dot (`com`) is refused as too broad.
+///
+/// Obtained with [`Agent::cookie_store()`](crate::Agent::cookie_store).
+#[derive(Debug, Default)]
+pub struct CookieStore {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieStore {
+    /// Write all unexpired cookies in the Netscape `cookies.txt` format.
+    ///
+    /// That is the format of curl's `--cookie-jar` and many browser
+    /// extensions: one cookie per line with the tab separated fields
+    ///
+    /// ```text
+    /// domain  include-subdomains  path  secure  expires  name  value
+    /// ```
+    ///
+    /// where the flags are `TRUE` or `FALSE` and `expires` is in seconds
+    /// since the unix epoch, `0` for a cookie that lasts for the session.
+    /// The domain of an `HttpOnly` cookie has a `#HttpOnly_` prefix, other
+    /// lines starting with `#` are comments.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    /// let file = std::fs::File::create("cookies.txt")?;
+    /// agent.cookie_store().save(file)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn save(&self, mut w: impl Write) -> io::Result<()> {
+        let now = SystemTime::now();
+        writeln!(w, "# Netscape HTTP Cookie File")?;
+        for c in self.cookies.iter().filter(|c| !c.is_expired(now)) {
+            let expires = c
+                .expires
+                .and_then(|e| e.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let prefix = if c.http_only { HTTP_ONLY_PREFIX } else { "" };
+            writeln!(
+                w,
+                "{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                prefix,
+                c.domain,
+                flag(!c.host_only),
+                c.path,
+                flag(c.secure),
+                expires,
+                c.name,
+                c.value
+            )?;
+        }
+        w.flush()
+    }
+
+    /// Replace the cookies with those saved by [`save()`](CookieStore::save).
+    ///
+    /// Expired cookies are skipped. A malformed line is an
+    /// [`io::ErrorKind::InvalidData`] error and leaves the store unchanged.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    /// let file = std::fs::File::open("cookies.txt")?;
+    /// agent.cookie_store().load(file)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    ///
+    /// `HttpOnly` cookies, as curl saves them, are kept as such:
+    ///
+    /// ```
+    /// let saved = "#HttpOnly_example.com\tFALSE\t/\tTRUE\t0\tsession\tabc\n";
+    /// let agent = ureq::agent();
+    /// agent.cookie_store().load(saved.as_bytes())?;
+    ///
+    /// let mut out = vec![];
+    /// agent.cookie_store().save(&mut out)?;
+    /// assert!(String::from_utf8(out).unwrap().ends_with(saved));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn load(&mut self, r: impl Read) -> io::Result<()> {
+        let now = SystemTime::now();
+        let mut cookies = vec![];
+        for (n, line) in BufReader::new(r).lines().enumerate() {
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            let is_comment = line.starts_with('#') && !line.starts_with(HTTP_ONLY_PREFIX);
+            if line.trim().is_empty() || is_comment {
+                continue;
+            }
+            let cookie = parse_cookie_line(line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed cookie on line {}", n + 1),
+                )
+            })?;
+            if !cookie.is_expired(now) {
+                cookies.push(cookie);
+            }
+        }
+        self.cookies = cookies;
+        Ok(())
+    }
+
+    /// Record the `Set-Cookie` headers of a response to `url`.
+    pub(crate) fn store_response_cookies<'a>(
+        &mut self,
+        url: &Url,
+        set_cookies: impl Iterator<Item = &'a str>,
+    ) {
+        let now = SystemTime::now();
+        for header in set_cookies {
+            if let Some(cookie) = parse_set_cookie(header, url, now) {
+                self.cookies.retain(|c| {
+                    !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path)
+                });
+                // an expiry in the past is how servers delete cookies
+                if !cookie.is_expired(now) {
+                    self.cookies.push(cookie);
+                }
+            }
+        }
+        self.cookies.retain(|c| !c.is_expired(now));
+    }
+
+    /// The `Cookie` header value for a request to `url`, if any cookie matches.
+    pub(crate) fn cookie_header(&self, url: &Url) -> Option<String> {
+        let now = SystemTime::now();
+        let mut matching: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|c| !c.is_expired(now) && c.matches(url))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        // more specific paths first, RFC 6265 5.4
+        matching.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        let pairs: Vec<String> = matching
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+}
+
+// Marks an `HttpOnly` cookie in a `cookies.txt` line.
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+fn flag(b: bool) -> &'static str {
+    if b {
+        "TRUE"
+    } else {
+        "FALSE"
+    }
+}
+
+// one line of the format written by CookieStore::save
+fn parse_cookie_line(line: &str) -> Option<Cookie> {
+    let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+        Some(line) => (line, true),
+        None => (line, false),
+    };
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 7 {
+        return None;
+    }
+    let parse_flag = |f: &str| match f {
+        "TRUE" => Some(true),
+        "FALSE" => Some(false),
+        _ => None,
+    };
+    let expires = match fields[4].parse::<u64>().ok()? {
+        0 => None,
+        secs => Some(UNIX_EPOCH.checked_add(Duration::from_secs(secs))?),
+    };
+    let name = fields[5];
+    let value = fields[6];
+    if name.is_empty() || !valid_cookie_text(name) || !valid_cookie_text(value) {
+        return None;
+    }
+    Some(Cookie {
+        name: name.to_string(),
+        value: value.to_string(),
+        domain: fields[0].trim_start_matches('.').to_ascii_lowercase(),
+        host_only: !parse_flag(fields[1])?,
+        path: fields[2].to_string(),
+        secure: parse_flag(fields[3])?,
+        http_only,
+        expires,
+    })
+}
+
+// Longer lifetimes are cut to 400 days, like browsers do.
+const MAX_AGE_LIMIT: u64 = 400 * 24 * 60 * 60;
+
+fn parse_set_cookie(header: &str, url: &Url, now: SystemTime) -> Option<Cookie> {
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    let value = value.trim().trim_matches('"');
+    if name.is_empty() || !valid_cookie_text(name) || !valid_cookie_text(value) {
+        return None;
+    }
+
+    let host = url.host_str().to_ascii_lowercase();
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url.path()),
+        secure: false,
+        http_only: false,
+        expires: None,
+    };
+    let mut max_age = None;
+
+    for attr in parts {
+        let (key, val) = match attr.split_once('=') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => (attr.trim(), ""),
+        };
+        match key.to_ascii_lowercase().as_str() {
+            "domain" => {
+                let domain = val.trim_start_matches('.').to_ascii_lowercase();
+                if domain.is_empty() {
+                    continue;
+                }
+                if !domain.contains('.') || !domain_match(&host, &domain) {
+                    // a cookie for some other site, or for a whole TLD
+                    return None;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+            "path" if val.starts_with('/') => cookie.path = val.to_string(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "max-age" => {
+                if let Ok(secs) = val.parse::<i64>() {
+                    max_age = Some(secs);
+                }
+            }
+            "expires" => {
+                if let Some(at) = parse_http_date(val) {
+                    cookie.expires = Some(at);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Max-Age wins over Expires
+    if let Some(secs) = max_age {
+        cookie.expires = Some(if secs <= 0 {
+            SystemTime::UNIX_EPOCH
+        } else {
+            now + Duration::from_secs((secs as u64).min(MAX_AGE_LIMIT))
+        });
+    }
+
+    Some(cookie)
+}
+
+// cookie-octet of RFC 6265 4.1.1, minus the checks the parser already did
+fn valid_cookie_text(s: &str) -> bool {
+    s.bytes()
+        .all(|c| c > 0x20 && c < 0x7f && c != b';' && c != b',' && c != b'\\' && c != b'"')
+}
+
+// RFC 6265 5.1.3
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.len() > domain.len()
+            && host.ends_with(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+}
+
+// RFC 6265 5.1.4
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+// RFC 6265 5.1.4
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+fn is_secure(url: &Url) -> bool {
+    !matches!(url.scheme(), Scheme::Http)
+}
+
+/// Exclusive access to an agent's [`CookieStore`].
+///
+/// Requests of the agent wait for it to be dropped.
+pub struct CookieStoreGuard<'a>(pub(crate) MutexGuard<'a, CookieStore>);
+
+impl<'a> Deref for CookieStoreGuard<'a> {
+    type Target = CookieStore;
+
+    fn deref(&self) -> &CookieStore {
+        &self.0
+    }
+}
+
+impl<'a> DerefMut for CookieStoreGuard<'a> {
+    fn deref_mut(&mut self) -> &mut CookieStore {
+        &mut self.0
+    }
+}
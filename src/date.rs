@@ -0,0 +1,93 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Parse the date formats found in HTTP headers, e.g. `Expires` or `Retry-After`.
+///
+/// This is the lenient algorithm of RFC 6265 5.1.1, which accepts the
+/// IMF-fixdate of RFC 7231 (`Sun, 06 Nov 1994 08:49:37 GMT`) as well as the
+/// obsolete RFC 850 and asctime forms and the dashed variant common in
+/// cookies. Dates are always taken to be in GMT.
+pub(crate) fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+
+    let tokens = s
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != ':')
+        .filter(|t| !t.is_empty());
+    for token in tokens {
+        if time.is_none() {
+            if let Some(t) = parse_time(token) {
+                time = Some(t);
+                continue;
+            }
+        }
+        if day.is_none() && token.len() <= 2 {
+            if let Ok(d) = token.parse::<u64>() {
+                day = Some(d);
+                continue;
+            }
+        }
+        if month.is_none() && token.len() >= 3 {
+            let prefix = token[..3].to_ascii_lowercase();
+            if let Some(m) = MONTHS.iter().position(|m| *m == prefix) {
+                month = Some(m as u64 + 1);
+                continue;
+            }
+        }
+        if year.is_none() && (2..=4).contains(&token.len()) {
+            if let Ok(y) = token.parse::<u64>() {
+                year = Some(y);
+                continue;
+            }
+        }
+    }
+
+    let (hour, min, sec) = time?;
+    let day = day?;
+    let month = month?;
+    let year = match year? {
+        y @ 70..=99 => y + 1900,
+        y @ 0..=69 => y + 2000,
+        y => y,
+    };
+    if !(1..=31).contains(&day) || year < 1970 || hour > 23 || min > 59 || sec > 59 {
+        return None;
+    }
+
+    let secs = days_since_epoch(year, month, day) * 86_400 + hour * 3_600 + min * 60 + sec;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+// hh:mm:ss, with one or two digits each
+fn parse_time(token: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = token.split(':');
+    let mut next = || -> Option<u64> {
+        let p = parts.next()?;
+        if p.is_empty() || p.len() > 2 {
+            return None;
+        }
+        p.parse().ok()
+    };
+    let t = (next()?, next()?, next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(t)
+}
+
+// Days from 1970-01-01 to the given date in the proleptic Gregorian calendar,
+// see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
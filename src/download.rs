@@ -0,0 +1,99 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::agent::Agent;
+use crate::error::{Error, ErrorKind};
+use crate::response::Response;
+use crate::url::Url;
+
+/// See [`Agent::download_resumable()`].
+pub(crate) fn download_resumable(agent: &Agent, url: Url, path: &Path) -> Result<u64, Error> {
+    let state = state_path(path);
+    // only a download left unfinished has a validator saved
+    let have = match (fs::read_to_string(&state), fs::metadata(path)) {
+        (Ok(validator), Ok(meta)) if meta.len() > 0 => Some((validator, meta.len())),
+        _ => None,
+    };
+
+    let resp = match &have {
+        Some((validator, len)) => {
+            let req = agent
+                .get(url)
+                .range(*len, None)
+                .set("If-Range", validator.trim());
+            match req.call() {
+                // nothing left to download
+                Err(Error::Status(416, resp)) if range_total(&resp) == Some(*len) => {
+                    fs::remove_file(&state)?;
+                    return Ok(*len);
+                }
+                result => result?,
+            }
+        }
+        None => agent.get(url).call()?,
+    };
+
+    let resumed = match (&have, resp.status_code()) {
+        (Some((_, len)), 206) => {
+            if range_start(&resp) != Some(*len) {
+                return Err(ErrorKind::BadHeader.msg("Content-Range doesn't continue the file"));
+            }
+            true
+        }
+        _ => false,
+    };
+
+    // If-Range takes a strong ETag or a Last-Modified date, without either
+    // an interrupted download starts over
+    let validator = resp
+        .header("etag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| resp.header("last-modified"))
+        .map(|v| v.to_string());
+    match &validator {
+        Some(validator) => fs::write(&state, validator)?,
+        None => {
+            let _ = fs::remove_file(&state);
+        }
+    }
+
+    let mut file = match resumed {
+        true => OpenOptions::new().append(true).open(path)?,
+        false => File::create(path)?,
+    };
+    let expected = resp.content_length();
+    let mut reader = resp.into_reader();
+    let copied = io::copy(&mut reader, &mut file)?;
+    file.sync_all()?;
+    // a connection closed early ends the body without an error
+    if expected.map(|len| len != copied).unwrap_or(false) {
+        let err = io::Error::new(io::ErrorKind::UnexpectedEof, "download cut short");
+        return Err(err.into());
+    }
+    if validator.is_some() {
+        fs::remove_file(&state)?;
+    }
+    Ok(file.metadata()?.len())
+}
+
+// The validator of an unfinished download is kept next to it, in
+// `<file>.ureq-resume`.
+fn state_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".ureq-resume");
+    PathBuf::from(name)
+}
+
+// Content-Range: bytes 500-999/1000 => 500
+fn range_start(resp: &Response) -> Option<u64> {
+    let range = resp.header("content-range")?.strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+// Content-Range: bytes */1000 => 1000
+fn range_total(resp: &Response) -> Option<u64> {
+    let range = resp.header("content-range")?;
+    range.rsplit('/').next()?.trim().parse().ok()
+}
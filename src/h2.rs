@@ -0,0 +1,511 @@
+//! HTTP/2 over TLS, RFC 7540, for servers that pick it with ALPN.
+//!
+//! Each connection carries a single request, on stream 1, and is closed
+//! with the response rather than pooled.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::body::{BodySize, SizedReader};
+use crate::error::{Error, ErrorKind};
+use crate::header::Headers;
+use crate::hpack::{self, Field};
+use crate::request::Request;
+use crate::response::Response;
+use crate::stream::Stream;
+use crate::timings::Timings;
+use crate::unit::request_headers;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// frame types, 6.1 to 6.10
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// flags
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+// settings, 6.5.2
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+
+/// The only stream of a connection.
+const STREAM_ID: u32 = 1;
+/// Frames are never larger than this, the initial `SETTINGS_MAX_FRAME_SIZE`.
+const MAX_FRAME_SIZE: usize = 16_384;
+/// How much response body the server may send ahead of our reads.
+const RECV_WINDOW: u32 = 1 << 20;
+/// The initial flow control window of both sides, 6.9.2.
+const DEFAULT_WINDOW: i64 = 65_535;
+
+/// Offer HTTP/2 with ALPN, unless the config has protocols of its own.
+pub(crate) fn with_alpn(config: Arc<rustls::ClientConfig>) -> Arc<rustls::ClientConfig> {
+    if !config.alpn_protocols.is_empty() {
+        return config;
+    }
+    let mut config = (*config).clone();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Arc::new(config)
+}
+
+/// Send `req` on a connection where the server picked HTTP/2.
+pub(crate) fn send(
+    req: &Request,
+    body: SizedReader,
+    stream: Stream,
+    mut timings: Timings,
+) -> Result<Response, Error> {
+    let mut conn = Connection {
+        stream,
+        decoder: hpack::Decoder::new(),
+        conn_window: DEFAULT_WINDOW,
+        stream_window: DEFAULT_WINDOW,
+        peer_initial_window: DEFAULT_WINDOW,
+        pending: None,
+    };
+    conn.start()?;
+
+    // a Host set by the user is the authority, like it is the Host for HTTP/1.1
+    let authority = match req.header("Host") {
+        Some(host) => host.to_string(),
+        None => req.url.host_header(),
+    };
+    let mut fields: Vec<(String, &str)> = vec![
+        (":method".into(), &req.method),
+        (":scheme".into(), "https"),
+        (":authority".into(), &authority),
+        (":path".into(), req.url.request_target()),
+    ];
+    // there is no chunking, DATA frames carry a body of any length
+    let size = match body.size {
+        BodySize::Unknown => BodySize::Empty,
+        size => size,
+    };
+    let headers = request_headers(req, size, body.bytes)?;
+    for (name, value) in &headers {
+        let name = name.to_ascii_lowercase();
+        // connection specific, 8.1.2.2, and replaced by :authority
+        if matches!(
+            name.as_str(),
+            "host"
+                | "connection"
+                | "keep-alive"
+                | "proxy-connection"
+                | "transfer-encoding"
+                | "upgrade"
+        ) || (name == "te" && value != "trailers")
+        {
+            continue;
+        }
+        fields.push((name, value));
+    }
+    let block = hpack::encode(fields.iter().map(|(n, v)| (n.as_str(), *v)));
+    let wire_log = req.agent.config.wire_log.as_ref();
+    if let Some(wire_log) = wire_log {
+        let fields = fields.iter().map(|(n, v)| (n.as_bytes(), v.as_bytes()));
+        wire_log.request(&wire_head(fields));
+    }
+
+    let empty = matches!(body.size, BodySize::Empty | BodySize::Known(0));
+    conn.send_headers(&block, empty)?;
+    if !empty {
+        conn.send_body(body.reader)?;
+    }
+
+    let (code, headers, done) = conn.read_response_head()?;
+    // as the head is decoded, good enough next to network latency
+    timings.first_byte = Some(Instant::now());
+    if let Some(wire_log) = wire_log {
+        let status = code.to_string();
+        let status = std::iter::once((&b":status"[..], status.as_bytes()));
+        wire_log.response(&wire_head(status.chain(headers.iter())));
+    }
+    let body = H2Body {
+        conn,
+        data: vec![],
+        pos: 0,
+        done,
+    };
+    Ok(Response::from_parts(
+        code,
+        headers,
+        Stream::H2(Box::new(body)),
+        timings,
+    ))
+}
+
+// The fields of a head as HTTP/1.1 style lines, for the wire log.
+fn wire_head<'a>(fields: impl Iterator<Item = (&'a [u8], &'a [u8])>) -> Vec<u8> {
+    let mut head = vec![];
+    for (name, value) in fields {
+        head.extend_from_slice(name);
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value.trim_ascii_start());
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+    head
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    /// The payload without padding and, for HEADERS, priority.
+    fn data(&self) -> io::Result<&[u8]> {
+        let mut p = &self.payload[..];
+        let mut pad = 0;
+        if self.flags & PADDED != 0 && matches!(self.kind, DATA | HEADERS) {
+            pad = *p
+                .first()
+                .ok_or_else(|| protocol_error("Missing padding length"))?
+                as usize;
+            p = &p[1..];
+        }
+        if self.flags & PRIORITY != 0 && self.kind == HEADERS {
+            p = p
+                .get(5..)
+                .ok_or_else(|| protocol_error("Missing priority"))?;
+        }
+        if pad > p.len() {
+            return Err(protocol_error("Too much padding"));
+        }
+        Ok(&p[..p.len() - pad])
+    }
+}
+
+struct Connection {
+    stream: Stream,
+    decoder: hpack::Decoder,
+    // what we may still send, 6.9
+    conn_window: i64,
+    stream_window: i64,
+    peer_initial_window: i64,
+    // a frame of our stream that came in while sending the body
+    pending: Option<Frame>,
+}
+
+impl Connection {
+    fn start(&mut self) -> io::Result<()> {
+        let mut settings = vec![];
+        for (id, value) in [
+            (SETTINGS_ENABLE_PUSH, 0),
+            (SETTINGS_INITIAL_WINDOW_SIZE, RECV_WINDOW),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+        let mut out = PREFACE.to_vec();
+        out.extend(frame_bytes(SETTINGS, 0, 0, &settings));
+        let increment = RECV_WINDOW - DEFAULT_WINDOW as u32;
+        out.extend(frame_bytes(WINDOW_UPDATE, 0, 0, &increment.to_be_bytes()));
+        self.stream.write_all(&out)
+    }
+
+    fn write_frame(
+        &mut self,
+        kind: u8,
+        flags: u8,
+        stream_id: u32,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        self.stream
+            .write_all(&frame_bytes(kind, flags, stream_id, payload))
+    }
+
+    fn send_headers(&mut self, block: &[u8], end_stream: bool) -> io::Result<()> {
+        let mut chunks: Vec<&[u8]> = block.chunks(MAX_FRAME_SIZE).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        let last = chunks.len() - 1;
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let (kind, mut flags) = match i {
+                0 if end_stream => (HEADERS, END_STREAM),
+                0 => (HEADERS, 0),
+                _ => (CONTINUATION, 0),
+            };
+            if i == last {
+                flags |= END_HEADERS;
+            }
+            self.write_frame(kind, flags, STREAM_ID, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn send_body(&mut self, mut reader: impl Read) -> io::Result<()> {
+        let mut buf = vec![0; MAX_FRAME_SIZE];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                v => v?,
+            };
+            if n == 0 {
+                return self.write_frame(DATA, END_STREAM, STREAM_ID, &[]);
+            }
+            let mut sent = 0;
+            while sent < n {
+                let window = self.conn_window.min(self.stream_window);
+                if window <= 0 {
+                    // wait for the server to make room, unless it answers
+                    // or resets the stream without the rest of the body
+                    if let Some(frame) = self.next_frame()? {
+                        self.pending = Some(frame);
+                        return Ok(());
+                    }
+                    continue;
+                }
+                let len = (n - sent).min(window as usize);
+                self.write_frame(DATA, 0, STREAM_ID, &buf[sent..sent + len])?;
+                self.conn_window -= len as i64;
+                self.stream_window -= len as i64;
+                sent += len;
+            }
+        }
+    }
+
+    /// The status, headers and whether the stream ended with them.
+    fn read_response_head(&mut self) -> Result<(u16, Headers, bool), Error> {
+        loop {
+            let frame = self.read_stream_frame()?;
+            match frame.kind {
+                HEADERS => {
+                    let end_stream = frame.flags & END_STREAM != 0;
+                    let fields = self.read_header_block(frame)?;
+                    let status = fields
+                        .iter()
+                        .find(|(name, _)| name == b":status")
+                        .and_then(|(_, value)| std::str::from_utf8(value).ok())
+                        .and_then(|value| value.parse::<u16>().ok())
+                        .ok_or_else(|| ErrorKind::BadStatus.msg("Missing HTTP/2 :status"))?;
+                    // informational, the final response follows
+                    if (100..200).contains(&status) && !end_stream {
+                        continue;
+                    }
+                    return Ok((status, to_headers(&fields)?, end_stream));
+                }
+                RST_STREAM => return Err(reset_error(&frame).into()),
+                _ => return Err(protocol_error("Expected HEADERS").into()),
+            }
+        }
+    }
+
+    fn read_header_block(&mut self, first: Frame) -> Result<Vec<Field>, Error> {
+        let mut block = first.data()?.to_vec();
+        let mut flags = first.flags;
+        while flags & END_HEADERS == 0 {
+            let frame = self.read_stream_frame()?;
+            if frame.kind != CONTINUATION {
+                return Err(protocol_error("Expected CONTINUATION").into());
+            }
+            block.extend_from_slice(&frame.payload);
+            flags = frame.flags;
+        }
+        self.decoder.decode(&block)
+    }
+
+    /// The next frame of our stream, handling those for the connection.
+    fn read_stream_frame(&mut self) -> io::Result<Frame> {
+        if let Some(frame) = self.pending.take() {
+            return Ok(frame);
+        }
+        loop {
+            if let Some(frame) = self.next_frame()? {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Read a frame, handing it out if it's one of our stream.
+    fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        let frame = self.read_frame()?;
+        match frame.kind {
+            SETTINGS if frame.flags & ACK == 0 => {
+                self.apply_settings(&frame.payload)?;
+                self.write_frame(SETTINGS, ACK, 0, &[])?;
+            }
+            PING if frame.flags & ACK == 0 => {
+                self.write_frame(PING, ACK, 0, &frame.payload)?;
+            }
+            WINDOW_UPDATE => {
+                let increment = u32_at(&frame.payload, 0)? & 0x7fff_ffff;
+                match frame.stream_id {
+                    0 => self.conn_window += increment as i64,
+                    STREAM_ID => self.stream_window += increment as i64,
+                    _ => {}
+                }
+            }
+            GOAWAY => {
+                let last_stream_id = u32_at(&frame.payload, 0)? & 0x7fff_ffff;
+                if last_stream_id < STREAM_ID {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "HTTP/2 server went away before the request",
+                    ));
+                }
+            }
+            PUSH_PROMISE => return Err(protocol_error("PUSH_PROMISE though disabled")),
+            _ if frame.stream_id == STREAM_ID => return Ok(Some(frame)),
+            // PRIORITY, acks, unknown types and other streams
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn read_frame(&mut self) -> io::Result<Frame> {
+        let mut head = [0; 9];
+        self.stream.read_exact(&mut head)?;
+        let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+        if len > MAX_FRAME_SIZE {
+            return Err(protocol_error("Frame larger than SETTINGS_MAX_FRAME_SIZE"));
+        }
+        let mut payload = vec![0; len];
+        self.stream.read_exact(&mut payload)?;
+        Ok(Frame {
+            kind: head[3],
+            flags: head[4],
+            stream_id: u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff,
+            payload,
+        })
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> io::Result<()> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(protocol_error("Malformed SETTINGS"));
+        }
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32_at(setting, 2)? as i64;
+            // the other settings only matter for features we don't use
+            if id == SETTINGS_INITIAL_WINDOW_SIZE {
+                self.stream_window += value - self.peer_initial_window;
+                self.peer_initial_window = value;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The response body, read from DATA frames.
+pub struct H2Body {
+    conn: Connection,
+    data: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl H2Body {
+    pub(crate) fn socket(&self) -> Option<&std::net::TcpStream> {
+        self.conn.stream.socket()
+    }
+
+    fn next_data(&mut self) -> io::Result<()> {
+        let frame = self.conn.read_stream_frame()?;
+        self.done = frame.flags & END_STREAM != 0;
+        match frame.kind {
+            DATA => {
+                self.data = frame.data()?.to_vec();
+                self.pos = 0;
+                // give back what the frame took of the windows
+                let len = frame.payload.len() as u32;
+                if len > 0 {
+                    let increment = len.to_be_bytes();
+                    self.conn.write_frame(WINDOW_UPDATE, 0, 0, &increment)?;
+                    if !self.done {
+                        self.conn
+                            .write_frame(WINDOW_UPDATE, 0, STREAM_ID, &increment)?;
+                    }
+                }
+            }
+            // trailers, decoded to keep the table in sync but not kept
+            HEADERS => {
+                self.conn
+                    .read_header_block(frame)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+            RST_STREAM => {
+                // NO_ERROR, the server didn't need the rest of the request
+                if u32_at(&frame.payload, 0)? != 0 {
+                    return Err(reset_error(&frame));
+                }
+                self.done = true;
+            }
+            _ => return Err(protocol_error("Unexpected frame in response body")),
+        }
+        Ok(())
+    }
+}
+
+impl Read for H2Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.data.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.next_data()?;
+        }
+        let n = (&self.data[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn frame_bytes(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(9 + payload.len());
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream_id.to_be_bytes());
+    out.extend_from_slice(payload);
+    out
+}
+
+fn u32_at(payload: &[u8], at: usize) -> io::Result<u32> {
+    payload
+        .get(at..at + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| protocol_error("Frame too short"))
+}
+
+// The fields as HTTP/1.1 style headers, without the pseudo-headers.
+fn to_headers(fields: &[Field]) -> Result<Headers, Error> {
+    let mut raw = vec![];
+    for (name, value) in fields.iter().filter(|(name, _)| !name.starts_with(b":")) {
+        raw.extend_from_slice(name);
+        raw.extend_from_slice(b": ");
+        raw.extend_from_slice(value);
+        raw.extend_from_slice(b"\r\n");
+    }
+    Headers::try_from(&raw[..])
+}
+
+fn reset_error(frame: &Frame) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionReset,
+        format!(
+            "HTTP/2 stream reset with error code {}",
+            u32_at(&frame.payload, 0).unwrap_or(0)
+        ),
+    )
+}
+
+fn protocol_error(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -1,71 +1,76 @@
 use crate::error::{Error, ErrorKind};
 use std::convert::TryFrom;
 
-#[derive(Clone, Copy)]
-struct Header {
-    meta: usize, // 0x00, 0x00, 0xcolon, 0xlen
-    data: [u8; 1024],
+// Where a header line is in the raw bytes, end excluding the CRLF.
+struct Line {
+    start: usize,
+    colon: usize,
+    end: usize,
 }
 
-pub struct Headers{
-    len: usize,
-    arr: [Header; 64],
-}
-
-impl Headers {
-    const fn new() -> Self {
-        Headers {
-            len: 0, arr: [Header{ meta: 0, data: [0; 1024] }; 64]
-        }
-    }
-
-    fn push(&mut self, t: Header) {
-        self.arr[self.len] = t;
-        self.len += 1;
-    }
+/// The headers of a response, as received. Sized to the head, so there is
+/// no limit on the number of headers or their length.
+pub struct Headers {
+    data: Vec<u8>,
+    lines: Vec<Line>,
 }
 
 impl TryFrom<&[u8]> for Headers {
     type Error = Error;
     fn try_from(v: &[u8]) -> Result<Self, Error> {
-        let mut map = Headers::new();
+        let mut lines = Vec::new();
         let mut start = 0;
         while let Some(len) = v[start..].windows(2).position(|x| x == b"\r\n") {
-            if len > 1024 {
-                return Err(ErrorKind::BadHeader.msg("HTTP header size larger than supported"));
+            let line = &v[start..start + len];
+            if line.contains(&b'\n') {
+                return Err(ErrorKind::BadHeader.msg("HTTP header line ends with a bare LF"));
             }
-            let colon = &v[start..start+len].iter().position(|x| *x == b':').ok_or_else(|| {
-                ErrorKind::BadHeader.msg("HTTP header must be a key-value separated by a colon")
-            })?;
-            let mut data = [0; 1024];
-            data[..len].copy_from_slice(&v[start..start+len]);
-
-            let meta = ((colon & 0xFFFF) << 16) | (len & 0xFFFF); 
-            let h = Header {
-                meta,
-                data,
-            };
-            map.push(h);
+            if matches!(line.first(), Some(b' ' | b'\t')) {
+                return Err(ErrorKind::BadHeader.msg("HTTP header line is folded"));
+            }
+            let colon = v[start..start + len]
+                .iter()
+                .position(|x| *x == b':')
+                .ok_or_else(|| {
+                    ErrorKind::BadHeader.msg("HTTP header must be a key-value separated by a colon")
+                })?;
+            lines.push(Line {
+                start,
+                colon: start + colon,
+                end: start + len,
+            });
             start += len + 2;
         }
-        Ok(map)
+        if start != v.len() {
+            return Err(ErrorKind::BadHeader.msg("HTTP header line must end with CRLF"));
+        }
+        Ok(Headers {
+            data: v.to_vec(),
+            lines,
+        })
     }
 }
 
 impl Headers {
+    /// All headers in the order received, as raw name and value bytes.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.lines.iter().map(move |line| {
+            (
+                &self.data[line.start..line.colon],
+                &self.data[line.colon + 1..line.end],
+            )
+        })
+    }
+
     pub fn header(&self, name: &str) -> Option<&[u8]> {
-        for header in &self.arr[..self.len] {
-            let meta = &header.meta;
-            let len = meta & 0xFFFF;
-            let colon = (meta >> 16) & 0xFFFF;
+        self.header_all(name).next()
+    }
 
-            let data_key = &header.data[..colon];
-            let v = &header.data[colon + 1..len];
-            if eq(name.trim().as_bytes(), data_key) {
-                return Some(v);
-            }
-        }
-        None
+    /// The values of all headers named `name`, in the order received.
+    pub fn header_all<'a: 'n, 'n>(&'a self, name: &'n str) -> impl Iterator<Item = &'a [u8]> + 'n {
+        self.iter()
+            .filter(move |(key, _)| eq(name.trim().as_bytes(), key))
+            .map(|(_, value)| value)
     }
 }
 
@@ -82,3 +87,17 @@ fn eq(given: &[u8], stored: &[u8]) -> bool {
     }
     true
 }
+
+// token = 1*tchar, RFC 7230 3.2.6
+pub(crate) fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c))
+}
+
+// Anything but control characters, which rules out smuggling extra header
+// lines through CR/LF. Horizontal tab is allowed.
+pub(crate) fn valid_value(value: &str) -> bool {
+    value.bytes().all(|c| c == b'\t' || !c.is_ascii_control())
+}
@@ -0,0 +1,359 @@
+//! HPACK, the header compression of HTTP/2, RFC 7541.
+//!
+//! Requests are encoded without compression, which every server accepts.
+//! Responses are decoded in full, since servers use all of it.
+
+use std::collections::{HashMap, VecDeque};
+
+use once_cell::sync::Lazy;
+
+use crate::error::{Error, ErrorKind};
+
+/// The dynamic table size we allow servers, the default of
+/// `SETTINGS_HEADER_TABLE_SIZE`.
+const MAX_TABLE_SIZE: usize = 4096;
+
+// Appendix A
+static STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// Appendix B, (bits, code) by symbol, the last one being EOS.
+#[rustfmt::skip]
+static HUFFMAN: [(u8, u32); 257] = [
+    (13, 0x1ff8), (23, 0x7fffd8), (28, 0xfffffe2), (28, 0xfffffe3),
+    (28, 0xfffffe4), (28, 0xfffffe5), (28, 0xfffffe6), (28, 0xfffffe7),
+    (28, 0xfffffe8), (24, 0xffffea), (30, 0x3ffffffc), (28, 0xfffffe9),
+    (28, 0xfffffea), (30, 0x3ffffffd), (28, 0xfffffeb), (28, 0xfffffec),
+    (28, 0xfffffed), (28, 0xfffffee), (28, 0xfffffef), (28, 0xffffff0),
+    (28, 0xffffff1), (28, 0xffffff2), (30, 0x3ffffffe), (28, 0xffffff3),
+    (28, 0xffffff4), (28, 0xffffff5), (28, 0xffffff6), (28, 0xffffff7),
+    (28, 0xffffff8), (28, 0xffffff9), (28, 0xffffffa), (28, 0xffffffb),
+    (6, 0x14), (10, 0x3f8), (10, 0x3f9), (12, 0xffa),
+    (13, 0x1ff9), (6, 0x15), (8, 0xf8), (11, 0x7fa),
+    (10, 0x3fa), (10, 0x3fb), (8, 0xf9), (11, 0x7fb),
+    (8, 0xfa), (6, 0x16), (6, 0x17), (6, 0x18),
+    (5, 0x0), (5, 0x1), (5, 0x2), (6, 0x19),
+    (6, 0x1a), (6, 0x1b), (6, 0x1c), (6, 0x1d),
+    (6, 0x1e), (6, 0x1f), (7, 0x5c), (8, 0xfb),
+    (15, 0x7ffc), (6, 0x20), (12, 0xffb), (10, 0x3fc),
+    (13, 0x1ffa), (6, 0x21), (7, 0x5d), (7, 0x5e),
+    (7, 0x5f), (7, 0x60), (7, 0x61), (7, 0x62),
+    (7, 0x63), (7, 0x64), (7, 0x65), (7, 0x66),
+    (7, 0x67), (7, 0x68), (7, 0x69), (7, 0x6a),
+    (7, 0x6b), (7, 0x6c), (7, 0x6d), (7, 0x6e),
+    (7, 0x6f), (7, 0x70), (7, 0x71), (7, 0x72),
+    (8, 0xfc), (7, 0x73), (8, 0xfd), (13, 0x1ffb),
+    (19, 0x7fff0), (13, 0x1ffc), (14, 0x3ffc), (6, 0x22),
+    (15, 0x7ffd), (5, 0x3), (6, 0x23), (5, 0x4),
+    (6, 0x24), (5, 0x5), (6, 0x25), (6, 0x26),
+    (6, 0x27), (5, 0x6), (7, 0x74), (7, 0x75),
+    (6, 0x28), (6, 0x29), (6, 0x2a), (5, 0x7),
+    (6, 0x2b), (7, 0x76), (6, 0x2c), (5, 0x8),
+    (5, 0x9), (6, 0x2d), (7, 0x77), (7, 0x78),
+    (7, 0x79), (7, 0x7a), (7, 0x7b), (15, 0x7ffe),
+    (11, 0x7fc), (14, 0x3ffd), (13, 0x1ffd), (28, 0xffffffc),
+    (20, 0xfffe6), (22, 0x3fffd2), (20, 0xfffe7), (20, 0xfffe8),
+    (22, 0x3fffd3), (22, 0x3fffd4), (22, 0x3fffd5), (23, 0x7fffd9),
+    (22, 0x3fffd6), (23, 0x7fffda), (23, 0x7fffdb), (23, 0x7fffdc),
+    (23, 0x7fffdd), (23, 0x7fffde), (24, 0xffffeb), (23, 0x7fffdf),
+    (24, 0xffffec), (24, 0xffffed), (22, 0x3fffd7), (23, 0x7fffe0),
+    (24, 0xffffee), (23, 0x7fffe1), (23, 0x7fffe2), (23, 0x7fffe3),
+    (23, 0x7fffe4), (21, 0x1fffdc), (22, 0x3fffd8), (23, 0x7fffe5),
+    (22, 0x3fffd9), (23, 0x7fffe6), (23, 0x7fffe7), (24, 0xffffef),
+    (22, 0x3fffda), (21, 0x1fffdd), (20, 0xfffe9), (22, 0x3fffdb),
+    (22, 0x3fffdc), (23, 0x7fffe8), (23, 0x7fffe9), (21, 0x1fffde),
+    (23, 0x7fffea), (22, 0x3fffdd), (22, 0x3fffde), (24, 0xfffff0),
+    (21, 0x1fffdf), (22, 0x3fffdf), (23, 0x7fffeb), (23, 0x7fffec),
+    (21, 0x1fffe0), (21, 0x1fffe1), (22, 0x3fffe0), (21, 0x1fffe2),
+    (23, 0x7fffed), (22, 0x3fffe1), (23, 0x7fffee), (23, 0x7fffef),
+    (20, 0xfffea), (22, 0x3fffe2), (22, 0x3fffe3), (22, 0x3fffe4),
+    (23, 0x7ffff0), (22, 0x3fffe5), (22, 0x3fffe6), (23, 0x7ffff1),
+    (26, 0x3ffffe0), (26, 0x3ffffe1), (20, 0xfffeb), (19, 0x7fff1),
+    (22, 0x3fffe7), (23, 0x7ffff2), (22, 0x3fffe8), (25, 0x1ffffec),
+    (26, 0x3ffffe2), (26, 0x3ffffe3), (26, 0x3ffffe4), (27, 0x7ffffde),
+    (27, 0x7ffffdf), (26, 0x3ffffe5), (24, 0xfffff1), (25, 0x1ffffed),
+    (19, 0x7fff2), (21, 0x1fffe3), (26, 0x3ffffe6), (27, 0x7ffffe0),
+    (27, 0x7ffffe1), (26, 0x3ffffe7), (27, 0x7ffffe2), (24, 0xfffff2),
+    (21, 0x1fffe4), (21, 0x1fffe5), (26, 0x3ffffe8), (26, 0x3ffffe9),
+    (28, 0xffffffd), (27, 0x7ffffe3), (27, 0x7ffffe4), (27, 0x7ffffe5),
+    (20, 0xfffec), (24, 0xfffff3), (20, 0xfffed), (21, 0x1fffe6),
+    (22, 0x3fffe9), (21, 0x1fffe7), (21, 0x1fffe8), (23, 0x7ffff3),
+    (22, 0x3fffea), (22, 0x3fffeb), (25, 0x1ffffee), (25, 0x1ffffef),
+    (24, 0xfffff4), (24, 0xfffff5), (26, 0x3ffffea), (23, 0x7ffff4),
+    (26, 0x3ffffeb), (27, 0x7ffffe6), (26, 0x3ffffec), (26, 0x3ffffed),
+    (27, 0x7ffffe7), (27, 0x7ffffe8), (27, 0x7ffffe9), (27, 0x7ffffea),
+    (27, 0x7ffffeb), (28, 0xffffffe), (27, 0x7ffffec), (27, 0x7ffffed),
+    (27, 0x7ffffee), (27, 0x7ffffef), (27, 0x7fffff0), (26, 0x3ffffee),
+    (30, 0x3fffffff),
+];
+
+static HUFFMAN_DECODE: Lazy<HashMap<(u8, u32), u8>> = Lazy::new(|| {
+    HUFFMAN[..256]
+        .iter()
+        .enumerate()
+        .map(|(sym, code)| (*code, sym as u8))
+        .collect()
+});
+
+/// A header field as decoded, name and value.
+pub(crate) type Field = (Vec<u8>, Vec<u8>);
+
+/// Decodes the header blocks of one connection, which share a dynamic
+/// table, so they must be decoded in the order received.
+pub(crate) struct Decoder {
+    // newest first
+    table: VecDeque<Field>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: MAX_TABLE_SIZE,
+        }
+    }
+
+    /// Decode a complete header block, i.e. with its continuations.
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<Field>, Error> {
+        self.decode_fields(block)
+            .ok_or_else(|| ErrorKind::BadHeader.msg("Malformed HTTP/2 header block"))
+    }
+
+    fn decode_fields(&mut self, block: &[u8]) -> Option<Vec<Field>> {
+        let mut fields = vec![];
+        let mut pos = 0;
+        while pos < block.len() {
+            let b = block[pos];
+            if b & 0x80 != 0 {
+                // 6.1 indexed
+                let index = integer(block, &mut pos, 7)?;
+                fields.push(self.get(index)?);
+            } else if b & 0x40 != 0 {
+                // 6.2.1 literal, added to the table
+                let field = self.literal(block, &mut pos, 6)?;
+                self.insert(field.clone());
+                fields.push(field);
+            } else if b & 0x20 != 0 {
+                // 6.3 table size update
+                let size = integer(block, &mut pos, 5)?;
+                if size > MAX_TABLE_SIZE {
+                    return None;
+                }
+                self.max_size = size;
+                self.evict();
+            } else {
+                // 6.2.2 and 6.2.3 literal, not added to the table
+                fields.push(self.literal(block, &mut pos, 4)?);
+            }
+        }
+        Some(fields)
+    }
+
+    fn literal(&self, block: &[u8], pos: &mut usize, prefix: u8) -> Option<Field> {
+        let name = match integer(block, pos, prefix)? {
+            0 => string(block, pos)?,
+            index => self.get(index)?.0,
+        };
+        let value = string(block, pos)?;
+        Some((name, value))
+    }
+
+    fn get(&self, index: usize) -> Option<Field> {
+        match index {
+            0 => None,
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Some((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            }
+            _ => self.table.get(index - 62).cloned(),
+        }
+    }
+
+    // 4.4
+    fn insert(&mut self, field: Field) {
+        let size = entry_size(&field);
+        if size > self.max_size {
+            self.table.clear();
+            self.size = 0;
+            return;
+        }
+        self.size += size;
+        self.table.push_front(field);
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.table.pop_back() {
+                Some(field) => self.size -= entry_size(&field),
+                None => break,
+            }
+        }
+    }
+}
+
+// 4.1
+fn entry_size((name, value): &Field) -> usize {
+    name.len() + value.len() + 32
+}
+
+// 5.1
+fn integer(block: &[u8], pos: &mut usize, prefix: u8) -> Option<usize> {
+    let mask = ((1u16 << prefix) - 1) as u8;
+    let first = *block.get(*pos)? & mask;
+    *pos += 1;
+    if first < mask {
+        return Some(first as usize);
+    }
+    let mut value = mask as usize;
+    let mut shift = 0;
+    loop {
+        let b = *block.get(*pos)?;
+        *pos += 1;
+        value = value.checked_add(((b & 0x7f) as usize) << shift)?;
+        if b & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+        // more than any sane length or index
+        if shift > 28 {
+            return None;
+        }
+    }
+}
+
+// 5.2
+fn string(block: &[u8], pos: &mut usize) -> Option<Vec<u8>> {
+    let huffman = *block.get(*pos)? & 0x80 != 0;
+    let len = integer(block, pos, 7)?;
+    let raw = block.get(*pos..pos.checked_add(len)?)?;
+    *pos += len;
+    if huffman {
+        huffman_decode(raw)
+    } else {
+        Some(raw.to_vec())
+    }
+}
+
+fn huffman_decode(raw: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(raw.len() * 8 / 5);
+    let (mut code, mut bits) = (0u32, 0u8);
+    for byte in raw {
+        for i in (0..8).rev() {
+            code = (code << 1) | ((byte >> i) & 1) as u32;
+            bits += 1;
+            if let Some(sym) = HUFFMAN_DECODE.get(&(bits, code)) {
+                out.push(*sym);
+                code = 0;
+                bits = 0;
+            } else if bits >= 30 {
+                // EOS, or no code at all
+                return None;
+            }
+        }
+    }
+    // padded with the most significant bits of EOS, all ones
+    if bits > 7 || code != (1 << bits) - 1 {
+        return None;
+    }
+    Some(out)
+}
+
+/// Encode header fields as literals without indexing and without Huffman
+/// coding. Names must be lowercase already.
+pub(crate) fn encode<'a>(fields: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<u8> {
+    let mut block = vec![];
+    for (name, value) in fields {
+        // 6.2.2 with a new name
+        block.push(0);
+        encode_string(&mut block, name.as_bytes());
+        encode_string(&mut block, value.as_bytes());
+    }
+    block
+}
+
+fn encode_string(block: &mut Vec<u8>, s: &[u8]) {
+    // the H bit stays 0
+    encode_integer(block, 7, s.len());
+    block.extend_from_slice(s);
+}
+
+// 5.1
+fn encode_integer(block: &mut Vec<u8>, prefix: u8, value: usize) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        block.push(value as u8);
+        return;
+    }
+    block.push(mask as u8);
+    let mut rest = value - mask;
+    while rest >= 0x80 {
+        block.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    block.push(rest as u8);
+}
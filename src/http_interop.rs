@@ -0,0 +1,69 @@
+use std::convert::TryFrom;
+use std::io::Read;
+
+use crate::agent::DEFAULT_AGENT;
+use crate::error::{Error, ErrorKind};
+use crate::response::{Response, ResponseReader};
+use crate::url::Url;
+
+/// Make a request described by an [`http::Request`].
+///
+/// A non-empty body is sent like [`Request::send()`](crate::Request::send).
+///
+/// ```no_run
+/// let req = http::Request::get("http://example.com/").body(std::io::empty()).unwrap();
+/// let resp = ureq::request_http(req)?;
+/// # Ok::<(), ureq::Error>(())
+/// ```
+pub fn request_http<R: Read>(req: http::Request<R>) -> Result<Response, Error> {
+    let (parts, mut body) = req.into_parts();
+    let url = Url::parse(&parts.uri.to_string())?;
+    let mut req = DEFAULT_AGENT.request(parts.method.as_str(), url);
+    for (name, value) in &parts.headers {
+        let value = value
+            .to_str()
+            .map_err(|e| ErrorKind::BadHeader.msg("Header value is not ASCII").src(e))?;
+        // Pushed rather than set() to keep repeated headers.
+        req.headers.push((name.to_string(), value.to_string()));
+    }
+    // Probe for a body so that body-less requests aren't sent chunked.
+    let mut first = [0; 1];
+    match body.read(&mut first)? {
+        0 => req.call(),
+        n => req.send((&first[..n]).chain(body)),
+    }
+}
+
+/// Converts status and headers, the body becomes a [`ResponseReader`].
+impl TryFrom<Response> for http::Response<ResponseReader> {
+    type Error = Error;
+
+    fn try_from(resp: Response) -> Result<Self, Error> {
+        let version = match resp.http_version() {
+            "HTTP/1.0" => http::Version::HTTP_10,
+            "HTTP/2.0" => http::Version::HTTP_2,
+            _ => http::Version::HTTP_11,
+        };
+        let mut builder = http::Response::builder()
+            .status(resp.status_code())
+            .version(version);
+        for (name, value) in resp.raw_headers() {
+            builder = builder.header(name, value.trim_ascii());
+        }
+        builder.body(resp.into_reader()).map_err(|e| {
+            ErrorKind::BadHeader
+                .msg("Header not valid for http crate")
+                .src(e)
+        })
+    }
+}
+
+/// Converts status and headers. The body is dropped unread.
+impl TryFrom<Response> for http::response::Parts {
+    type Error = Error;
+
+    fn try_from(resp: Response) -> Result<Self, Error> {
+        let (parts, _) = http::Response::<ResponseReader>::try_from(resp)?.into_parts();
+        Ok(parts)
+    }
+}
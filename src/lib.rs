@@ -57,16 +57,48 @@
 //! `ureq = { version = "*", features = ["json", "charset"] }`
 //!
 //! * `tls` enables https. This is enabled by default.
+//! * `native-roots` makes https trust the root certificates of the OS
+//!   trust store, rather than the bundled [webpki-roots](https://docs.rs/webpki-roots).
+//!   Only the roots are loaded, certificates are still checked by rustls,
+//!   not by the OS, so its revocation checks and policies don't apply.
+//! * `json` enables [Request::send_json()] for sending serde serializable
+//!   bodies and [Response::into_json()] for deserializing them, and
+//!   [Request::query_struct()] for query strings from serde structs.
+//! * `cookies` makes an agent keep the cookies servers set and send them
+//!   back on later requests. They can be saved and loaded through
+//!   [Agent::cookie_store()].
+//! * `charset` makes [Response::into_string()] decode bodies from the charset
+//!   given in the `Content-Type` header rather than only accepting UTF-8.
+//! * `vcr` enables recording requests to disk and replaying them in later
+//!   runs, see [Cassette].
+//! * `gzip` enables compressing request bodies with
+//!   [Request::gzip_body()].
+//! * `testing` exposes `Stream::from_vec` and `Response::from_stream`, and
+//!   the [testing] module of canned responses, so code using ureq can be
+//!   unit tested without a server.
+//! * `http-interop` converts to and from the types of the [http](https://docs.rs/http)
+//!   crate, `http::Request` via [request_http()] and [Response] via `TryFrom`.
+//! * `idna` lets urls have non-ASCII hostnames, like `https://bücher.example/`,
+//!   by converting them to punycode. Without it they are an error.
+//! * `http2` speaks HTTP/2 to https servers that pick it during the TLS
+//!   handshake, and HTTP/1.1 to the others.
 //!
 //! # Plain requests
 //!
 //! Most standard methods (GET, POST, PUT etc), are supported as functions from the
-//! top of the library ([get()], [post()], [put()], etc).
+//! top of the library ([get()], [post()], [put()], [delete()], [patch()],
+//! [head()] and [options()]).
 //!
 //! These top level http method functions create a [Request] instance
 //! which follows a build pattern. The builders are finished using:
 //!
 //! * [`.call()`][Request::call()] without a request body.
+//! * [`.send_bytes()`][Request::send_bytes()] for a body that is a byte slice.
+//! * [`.send_string()`][Request::send_string()] for a body that is a string.
+//! * [`.send_json()`][Request::send_json()] for a body that is serialized to JSON.
+//! * [`.send_multipart()`][Request::send_multipart()] for a `multipart/form-data`
+//!   body built with [MultipartBuilder].
+//! * [`.send()`][Request::send()] for a body streamed from any reader.
 //!
 //! # Blocking I/O for simplicity
 //!
@@ -94,33 +126,110 @@
 
 mod agent;
 mod body;
+pub mod cache;
+mod cancel;
+#[cfg(feature = "cookies")]
+mod cookies;
+mod date;
+mod download;
 mod error;
+#[cfg(feature = "http2")]
+mod h2;
 mod header;
+#[cfg(feature = "http2")]
+mod hpack;
+#[cfg(feature = "http-interop")]
+mod http_interop;
+mod middleware;
+mod multipart;
+mod multiple;
+mod pool;
+mod proxy;
 mod readers;
 mod request;
+mod resolve;
 mod response;
+mod retry;
+mod sign;
+pub mod sse;
 mod stream;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod timings;
+#[cfg(feature = "tls")]
+mod tls;
 mod unit;
 mod url;
+#[cfg(feature = "vcr")]
+mod vcr;
+mod wire;
 
-#[doc(hidden)]
-pub use crate::error::Error;
-pub use crate::readers::{ConsumingReadIterator, ReadIterator, ReadToEndIterator};
+pub use crate::agent::{Agent, AgentBuilder, RedirectPolicy};
+pub use crate::cancel::CancelToken;
+#[cfg(feature = "cookies")]
+pub use crate::cookies::{CookieStore, CookieStoreGuard};
+pub use crate::error::{Error, ErrorKind, OrAnyStatus};
+#[cfg(feature = "http-interop")]
+pub use crate::http_interop::request_http;
+pub use crate::middleware::{Middleware, MiddlewareNext};
+pub use crate::multipart::MultipartBuilder;
+pub use crate::proxy::Proxy;
+pub use crate::readers::{ConsumingReadIterator, ReadIterator, ReadToEndIterator, TeeReader};
+pub use crate::request::Request;
+pub use crate::resolve::Resolver;
 pub use crate::response::{Response, ResponseReader, Status};
+pub use crate::retry::RetryPolicy;
+pub use crate::sign::SignRequest;
+#[cfg(feature = "testing")]
+pub use crate::stream::Stream;
+pub use crate::timings::Timings;
+#[cfg(feature = "tls")]
+pub use crate::tls::Certificate;
 pub use crate::url::Url;
+#[cfg(feature = "vcr")]
+pub use crate::vcr::{Cassette, VcrMode};
+pub use crate::wire::Wire;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-// is_test returns false so long as it has only ever been called with false.
-// If it has ever been called with true, it will always return true after that.
-// This is a public but hidden function used to allow doctests to use the test_agent.
-// Note that we use this approach for doctests rather the #[cfg(test)], because
-// doctests are run against a copy of the crate build without cfg(test) set.
-// We also can't use #[cfg(doctest)] to do this, because cfg(doctest) is only set
-// when collecting doctests, not when building the crate.
-#[doc(hidden)]
+/// An agent with the default configuration, to share cookies between requests.
+///
+/// Use [`AgentBuilder`] for any other configuration.
+pub fn agent() -> Agent {
+    AgentBuilder::new().build()
+}
 
 /// Make a GET request.
-pub fn get(path: &Url) -> Result<Response> {
-    agent::Agent::get(path)
+pub fn get(path: Url) -> Request {
+    agent::DEFAULT_AGENT.get(path)
+}
+
+/// Make a POST request.
+pub fn post(path: Url) -> Request {
+    agent::DEFAULT_AGENT.post(path)
+}
+
+/// Make a PUT request.
+pub fn put(path: Url) -> Request {
+    agent::DEFAULT_AGENT.put(path)
+}
+
+/// Make a DELETE request.
+pub fn delete(path: Url) -> Request {
+    agent::DEFAULT_AGENT.delete(path)
+}
+
+/// Make a PATCH request.
+pub fn patch(path: Url) -> Request {
+    agent::DEFAULT_AGENT.patch(path)
+}
+
+/// Make a HEAD request.
+pub fn head(path: Url) -> Request {
+    agent::DEFAULT_AGENT.head(path)
+}
+
+/// Make a OPTIONS request.
+pub fn options(path: Url) -> Request {
+    agent::DEFAULT_AGENT.options(path)
 }
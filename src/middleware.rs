@@ -0,0 +1,64 @@
+use crate::error::Error;
+use crate::request::Request;
+use crate::response::Response;
+
+/// Code run around every request of an agent, e.g. for logging, adding
+/// headers or refreshing credentials.
+///
+/// Middleware is added with
+/// [`AgentBuilder::middleware()`](crate::AgentBuilder::middleware) and runs
+/// in the order added. Each one passes the request on to the rest of the
+/// chain with [`MiddlewareNext::handle()`], possibly changed, or answers
+/// it without passing it on. The last in the chain sends it, following
+/// redirects and retrying as configured. Responses with 4xx and 5xx
+/// statuses pass through the chain as responses, they only become
+/// [`Error::Status`] after it.
+///
+/// Closures with the same signature as [`handle()`](Middleware::handle)
+/// are middleware.
+///
+/// ```no_run
+/// use std::time::Instant;
+///
+/// let agent = ureq::AgentBuilder::new()
+///     .middleware(|req: ureq::Request, next: ureq::MiddlewareNext| {
+///         let started = Instant::now();
+///         let url = req.url().to_string();
+///         let result = next.handle(req);
+///         println!("{} took {:?}", url, started.elapsed());
+///         result
+///     })
+///     .build();
+/// ```
+pub trait Middleware: Send + Sync + 'static {
+    /// Handle `request`, usually by passing it on to `next`.
+    fn handle(&self, request: Request, next: MiddlewareNext) -> Result<Response, Error>;
+}
+
+/// The rest of the middleware chain, and the sending of the request after
+/// it.
+pub struct MiddlewareNext<'a> {
+    pub(crate) chain: &'a mut dyn Iterator<Item = &'a dyn Middleware>,
+    // sends the request with its body, once the chain is done
+    pub(crate) send: Box<dyn FnOnce(Request) -> Result<Response, Error> + 'a>,
+}
+
+impl<'a> MiddlewareNext<'a> {
+    /// Pass the request on to the next middleware, or send it if this was
+    /// the last.
+    pub fn handle(self, request: Request) -> Result<Response, Error> {
+        match self.chain.next() {
+            Some(step) => step.handle(request, self),
+            None => (self.send)(request),
+        }
+    }
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Request, MiddlewareNext) -> Result<Response, Error> + Send + Sync + 'static,
+{
+    fn handle(&self, request: Request, next: MiddlewareNext) -> Result<Response, Error> {
+        self(request, next)
+    }
+}
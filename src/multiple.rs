@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+
+use crate::agent::Agent;
+use crate::error::Error;
+use crate::response::Response;
+use crate::url::Url;
+
+// Requests in flight at once, at most.
+const MAX_WORKERS: usize = 8;
+// Connections to one host at once, at most.
+const MAX_PER_HOST: usize = 2;
+
+/// See [`Agent::send_multiple()`].
+pub(crate) fn send_multiple(agent: &Agent, urls: Vec<Url>) -> Vec<Result<Response, Error>> {
+    let lanes = Mutex::new(lanes(&urls));
+    let workers = lanes.lock().unwrap().len().min(MAX_WORKERS);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|s| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let (urls, lanes) = (&urls, &lanes);
+            s.spawn(move || loop {
+                let lane = match lanes.lock().unwrap().pop() {
+                    Some(lane) => lane,
+                    None => break,
+                };
+                // one after the other, each reusing the connection the
+                // previous one gave back to the pool
+                for i in lane {
+                    let result = agent.get(urls[i].clone()).call();
+                    let _ = tx.send((i, result.and_then(Response::into_buffered)));
+                }
+            });
+        }
+    });
+    drop(tx);
+
+    let mut results: Vec<_> = rx.into_iter().collect();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+// The indexes of `urls` split into lanes sent one after the other, at most
+// MAX_PER_HOST for each scheme, host and port.
+fn lanes(urls: &[Url]) -> Vec<Vec<usize>> {
+    let mut hosts: HashMap<_, Vec<usize>> = HashMap::new();
+    for (i, url) in urls.iter().enumerate() {
+        let key = (
+            url.scheme(),
+            url.host_str().to_ascii_lowercase(),
+            url.port(),
+        );
+        hosts.entry(key).or_default().push(i);
+    }
+
+    let mut lanes = vec![];
+    for indexes in hosts.into_values() {
+        let count = indexes.len().min(MAX_PER_HOST);
+        let mut host_lanes = vec![vec![]; count];
+        for (n, i) in indexes.into_iter().enumerate() {
+            host_lanes[n % count].push(i);
+        }
+        lanes.extend(host_lanes);
+    }
+    lanes
+}
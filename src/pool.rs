@@ -0,0 +1,410 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Take};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::agent::Agent;
+use crate::cancel::CancelToken;
+use crate::header::Headers;
+use crate::proxy::Proxy;
+use crate::readers::{ChunkDecoder, ComboReader};
+use crate::request::Request;
+use crate::stream::Stream;
+use crate::url::Scheme;
+
+/// Idle keep-alive connections of an agent, for reuse by later requests
+/// to the same server.
+///
+/// When full, the connection idle the longest is closed to make room.
+/// Connections idle for too long, or too old, are closed rather than
+/// reused, since the server has likely closed its end already.
+pub(crate) struct ConnectionPool {
+    inner: Mutex<PoolInner>,
+    max_idle_connections: usize,
+    max_idle_connections_per_host: usize,
+    idle_timeout: Option<Duration>,
+    max_age: Option<Duration>,
+}
+
+struct PoolInner {
+    // the most recently returned stream of a key is last
+    recycle: HashMap<PoolKey, VecDeque<IdleStream>>,
+    // one entry per idle stream, least recently returned first
+    lru: VecDeque<PoolKey>,
+    // after a shutdown, connections are closed rather than kept
+    closed: bool,
+}
+
+struct IdleStream {
+    stream: Stream,
+    created: Instant,
+    // the earlier of the idle timeout and the max age running out
+    expires: Option<Instant>,
+}
+
+impl IdleStream {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires.map(|e| e <= now).unwrap_or(false)
+    }
+}
+
+/// Connections are only reused for the same scheme, host, port, proxy and
+/// address connected to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PoolKey {
+    scheme: Scheme,
+    host: String,
+    port: u16,
+    proxy: Option<Proxy>,
+    connect_to: Option<SocketAddr>,
+}
+
+impl PoolKey {
+    pub fn new(req: &Request) -> Self {
+        let url = &req.url;
+        PoolKey {
+            scheme: url.scheme(),
+            host: url.host_str().to_ascii_lowercase(),
+            port: url.port(),
+            proxy: req.agent.config.proxy.clone(),
+            connect_to: req.connect_addr(),
+        }
+    }
+}
+
+impl ConnectionPool {
+    pub fn new(
+        max_idle_connections: usize,
+        max_idle_connections_per_host: usize,
+        idle_timeout: Option<Duration>,
+        max_age: Option<Duration>,
+    ) -> Self {
+        ConnectionPool {
+            inner: Mutex::new(PoolInner {
+                recycle: HashMap::new(),
+                lru: VecDeque::new(),
+                closed: false,
+            }),
+            max_idle_connections,
+            max_idle_connections_per_host,
+            idle_timeout,
+            max_age,
+        }
+    }
+
+    /// Take the most recently returned, unexpired connection for `key`,
+    /// with the time it was established.
+    pub fn try_get_connection(&self, key: &PoolKey) -> Option<(Stream, Instant)> {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            let streams = inner.recycle.get_mut(key)?;
+            let idle = streams.pop_back();
+            if streams.is_empty() {
+                inner.recycle.remove(key);
+            }
+            let idle = idle?;
+            inner.remove_lru(key, true);
+            if !idle.is_expired(now) {
+                return Some((idle.stream, idle.created));
+            }
+        }
+    }
+
+    /// Keep `stream`, connected at `created`, for reuse. `server_timeout`
+    /// is how long the server said it keeps the connection open idle.
+    pub fn add(
+        &self,
+        key: PoolKey,
+        stream: Stream,
+        created: Instant,
+        server_timeout: Option<Duration>,
+    ) {
+        if self.max_idle_connections == 0 || self.max_idle_connections_per_host == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let idle_timeout = match (self.idle_timeout, server_timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        // too far out to represent is never
+        let idle_expires = idle_timeout.and_then(|t| now.checked_add(t));
+        let age_expires = self.max_age.and_then(|t| created.checked_add(t));
+        let expires = match (idle_expires, age_expires) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        if expires.map(|e| e <= now).unwrap_or(false) {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return;
+        }
+        inner.remove_expired(now);
+        let streams = inner.recycle.entry(key.clone()).or_default();
+        streams.push_back(IdleStream {
+            stream,
+            created,
+            expires,
+        });
+        if streams.len() > self.max_idle_connections_per_host {
+            streams.pop_front();
+            inner.remove_lru(&key, false);
+        }
+        inner.lru.push_back(key);
+
+        if inner.lru.len() > self.max_idle_connections {
+            let oldest = inner.lru.pop_front().expect("lru is not empty");
+            inner.drop_oldest(&oldest);
+        }
+    }
+
+    /// Close all idle connections, and those returned from now on.
+    pub fn shutdown(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.closed = true;
+        inner.recycle.clear();
+        inner.lru.clear();
+    }
+}
+
+impl PoolInner {
+    // Entries of the same key are interchangeable apart from their age,
+    // so the newest one goes with the newest stream.
+    fn remove_lru(&mut self, key: &PoolKey, newest: bool) {
+        let pos = if newest {
+            self.lru.iter().rposition(|k| k == key)
+        } else {
+            self.lru.iter().position(|k| k == key)
+        };
+        if let Some(pos) = pos {
+            self.lru.remove(pos);
+        }
+    }
+
+    fn remove_expired(&mut self, now: Instant) {
+        let mut removed = vec![];
+        self.recycle.retain(|key, streams| {
+            let before = streams.len();
+            streams.retain(|s| !s.is_expired(now));
+            for _ in streams.len()..before {
+                removed.push(key.clone());
+            }
+            !streams.is_empty()
+        });
+        for key in removed {
+            self.remove_lru(&key, false);
+        }
+    }
+
+    fn drop_oldest(&mut self, key: &PoolKey) {
+        if let Some(streams) = self.recycle.get_mut(key) {
+            streams.pop_front();
+            if streams.is_empty() {
+                self.recycle.remove(key);
+            }
+        }
+    }
+}
+
+/// Where the connection of a response goes once its body has been read.
+pub(crate) struct PoolReturn {
+    pub agent: Agent,
+    pub key: PoolKey,
+    /// When the connection was established, for the max age.
+    pub created: Instant,
+    /// From the response's `Keep-Alive` header, see [`keep_alive_hints()`].
+    pub server_timeout: Option<Duration>,
+    pub cancel: CancelToken,
+}
+
+// The longest idle timeout taken from a server.
+const MAX_KEEP_ALIVE_SECS: u64 = 60 * 60;
+
+/// The `timeout` and `max` of a `Keep-Alive: timeout=5, max=100` header:
+/// how long the server keeps the connection open idle, and how many more
+/// requests it takes on it.
+///
+/// The timeout is made a second shorter, the server started counting when
+/// it sent the response, and closing it at the same time as a request goes
+/// out fails that request. A timeout that isn't a number is ignored, and
+/// one over an hour is taken as an hour.
+pub(crate) fn keep_alive_hints(header: Option<&str>) -> (Option<Duration>, Option<u32>) {
+    let (mut timeout, mut max) = (None, None);
+    for param in header.unwrap_or("").split(',') {
+        match param.split_once('=') {
+            Some((name, value)) if name.trim().eq_ignore_ascii_case("timeout") => {
+                if let Ok(secs) = value.trim().parse::<u64>() {
+                    let secs = secs.min(MAX_KEEP_ALIVE_SECS);
+                    timeout = Some(Duration::from_secs(secs.saturating_sub(1)));
+                }
+            }
+            Some((name, value)) if name.trim().eq_ignore_ascii_case("max") => {
+                max = value.trim().parse().ok();
+            }
+            _ => {}
+        }
+    }
+    (timeout, max)
+}
+
+// Left of a body when it's dropped, read to keep the connection. For
+// more it's cheaper to connect again.
+const MAX_DRAIN: u64 = 64 * 1024;
+// How long dropping a body waits on the server for the rest of it.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A body reader that hands the connection back to the pool as soon as
+/// the body is fully read.
+///
+/// Dropped before that, the rest of the body is read and discarded if
+/// it's small, otherwise the connection is closed.
+pub(crate) struct PoolReturnRead<R: BodyRead> {
+    reader: Option<R>,
+    pool_return: Option<PoolReturn>,
+    // kept from the reader when it's done
+    trailers: Option<Box<Headers>>,
+}
+
+/// Body readers that know where the body ends.
+pub(crate) trait BodyRead: Read {
+    /// Whether the whole body was read, without another read.
+    fn is_done(&self) -> bool;
+    fn into_combo(self) -> ComboReader;
+    fn socket(&self) -> Option<&TcpStream>;
+    /// Headers sent after the body.
+    fn take_trailers(&mut self) -> Option<Box<Headers>> {
+        None
+    }
+}
+
+impl BodyRead for Take<ComboReader> {
+    fn is_done(&self) -> bool {
+        self.limit() == 0
+    }
+
+    fn into_combo(self) -> ComboReader {
+        self.into_inner()
+    }
+
+    fn socket(&self) -> Option<&TcpStream> {
+        self.get_ref().st.socket()
+    }
+}
+
+impl BodyRead for ChunkDecoder<ComboReader> {
+    // the terminating chunk is only seen by a read
+    fn is_done(&self) -> bool {
+        ChunkDecoder::is_done(self)
+    }
+
+    fn into_combo(self) -> ComboReader {
+        self.into_inner()
+    }
+
+    fn socket(&self) -> Option<&TcpStream> {
+        self.get_ref().st.socket()
+    }
+
+    fn take_trailers(&mut self) -> Option<Box<Headers>> {
+        ChunkDecoder::take_trailers(self)
+    }
+}
+
+impl<R: BodyRead> PoolReturnRead<R> {
+    pub fn new(pool_return: Option<PoolReturn>, reader: R) -> Self {
+        let mut r = PoolReturnRead {
+            reader: Some(reader),
+            pool_return,
+            trailers: None,
+        };
+        // e.g. Content-Length: 0
+        if r.reader.as_ref().map(|r| r.is_done()).unwrap_or(false) {
+            r.return_connection(true);
+        }
+        r
+    }
+
+    /// The trailers, once the body is read.
+    pub fn trailers(&self) -> Option<&Headers> {
+        self.trailers.as_deref()
+    }
+
+    // A connection whose body was cut short is closed instead.
+    fn return_connection(&mut self, reusable: bool) {
+        let mut reader = match self.reader.take() {
+            Some(r) => r,
+            None => return,
+        };
+        self.trailers = reader.take_trailers();
+        if let Some(PoolReturn {
+            agent,
+            key,
+            created,
+            server_timeout,
+            cancel,
+        }) = self.pool_return.take()
+        {
+            cancel.release();
+            match reader.into_combo().into_stream() {
+                Some(stream) if reusable => {
+                    agent.state.pool.add(key, stream, created, server_timeout);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: BodyRead> Read for PoolReturnRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let reader = match &mut self.reader {
+            Some(r) => r,
+            None => return Ok(0),
+        };
+        let pool_return = &self.pool_return;
+        let cancelled = || match pool_return {
+            Some(pool_return) => pool_return.cancel.is_cancelled(),
+            None => false,
+        };
+        let n = match reader.read(buf) {
+            // the socket was shut down under the body
+            Ok(0) | Err(_) if !buf.is_empty() && !reader.is_done() && cancelled() => {
+                let msg = "request was cancelled";
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, msg));
+            }
+            result => result?,
+        };
+        if reader.is_done() {
+            self.return_connection(true);
+        } else if n == 0 && !buf.is_empty() {
+            // the server closed the connection before the end of the body
+            self.return_connection(false);
+        }
+        Ok(n)
+    }
+}
+
+impl<R: BodyRead> Drop for PoolReturnRead<R> {
+    fn drop(&mut self) {
+        let reader = match (&mut self.reader, &self.pool_return) {
+            (Some(r), Some(p)) if !p.cancel.is_cancelled() => r,
+            _ => return self.return_connection(false),
+        };
+        if let Some(sock) = reader.socket() {
+            let timeout = match sock.read_timeout() {
+                Ok(Some(t)) if t < DRAIN_TIMEOUT => t,
+                _ => DRAIN_TIMEOUT,
+            };
+            // the next request sets its own timeout
+            let _ = sock.set_read_timeout(Some(timeout));
+        }
+        let drained = io::copy(&mut reader.by_ref().take(MAX_DRAIN), &mut io::sink());
+        let done = drained.is_ok() && reader.is_done();
+        self.return_connection(done);
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv6Addr, TcpStream};
+
+use crate::error::{Error, ErrorKind};
+use crate::unit::basic_auth;
+use crate::url::decode;
+
+/// An HTTP proxy that requests are tunneled through with `CONNECT`.
+///
+/// Both http and https urls are tunneled, so the proxy only learns the
+/// host and port of a request and https stays encrypted end to end.
+///
+/// Credentials are sent with Basic authentication. A proxy that answers
+/// `407 Proxy Authentication Required` asking for Digest authentication
+/// instead is sent the `CONNECT` again with that. If the proxy still
+/// refuses, it's an [`ErrorKind::ProxyUnauthorized`] error.
+///
+/// ```no_run
+/// let proxy = ureq::Proxy::new("localhost:3128")?;
+/// let agent = ureq::AgentBuilder::new().proxy(proxy).build();
+/// let url = ureq::Url::parse("https://example.com/")?;
+/// let resp = agent.get(url).call()?;
+/// # Ok::<(), ureq::Error>(())
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Proxy {
+    // without brackets for IPv6
+    host: String,
+    port: u16,
+    // user and password, decoded
+    credentials: Option<(String, String)>,
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Proxy({}:{}", self.host, self.port)?;
+        if self.credentials.is_some() {
+            write!(f, ", with credentials")?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Proxy {
+    /// A proxy at `host:port`, optionally prefixed with `http://`. The port
+    /// is 8080 if left out, and IPv6 addresses are bracketed, `[::1]:3128`.
+    ///
+    /// Credentials for Basic authentication go in front,
+    /// `user:password@host:port`, percent-encoded if they contain `:` or `@`.
+    ///
+    /// Anything else is an [`ErrorKind::InvalidProxyUrl`] error.
+    pub fn new(proxy: impl AsRef<str>) -> Result<Proxy, Error> {
+        let invalid = || ErrorKind::InvalidProxyUrl.new();
+        let s = proxy.as_ref().trim();
+        let s = s.strip_prefix("http://").unwrap_or(s);
+        let s = s.strip_suffix('/').unwrap_or(s);
+
+        let (credentials, s) = match s.rsplit_once('@') {
+            Some((userinfo, rest)) => {
+                let (user, pass) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (
+                    Some((decode(user).into_owned(), decode(pass).into_owned())),
+                    rest,
+                )
+            }
+            None => (None, s),
+        };
+
+        let (host, port) = match s.rfind(':') {
+            Some(c) if !s[c..].contains(']') => (&s[..c], Some(&s[c + 1..])),
+            _ => (s, None),
+        };
+        let port = match port {
+            Some(p) => p.parse::<u16>().map_err(|_| invalid())?,
+            None => 8080,
+        };
+        let valid = match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+            Some(ip) => ip.parse::<Ipv6Addr>().is_ok(),
+            None => {
+                !host.is_empty()
+                    && host
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b'_'))
+            }
+        };
+        if !valid {
+            return Err(invalid());
+        }
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        Ok(Proxy {
+            host: host.to_string(),
+            port,
+            credentials,
+        })
+    }
+
+    pub(crate) fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Set for IP literals, which are not resolved.
+    pub(crate) fn ip(&self) -> Option<IpAddr> {
+        self.host.parse().ok()
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+/// Have `proxy`, connected on `stream`, open a tunnel to `target`, a
+/// `host:port`. After that the stream talks to that server.
+///
+/// `reconnect` connects to the proxy again, should it close the connection
+/// when asking for other credentials.
+pub(crate) fn connect_tunnel(
+    stream: &mut TcpStream,
+    proxy: &Proxy,
+    target: &str,
+    user_agent: Option<&str>,
+    reconnect: &mut dyn FnMut() -> Result<TcpStream, Error>,
+) -> Result<(), Error> {
+    // Basic up front, it saves a round trip with most proxies
+    let basic = proxy
+        .credentials
+        .as_ref()
+        .map(|(user, pass)| basic_auth(user, pass));
+    let head = send_connect(stream, target, user_agent, basic.as_deref())?;
+    match head.status {
+        200..=299 => return Ok(()),
+        407 => {}
+        _ => return Err(ErrorKind::ProxyConnect.msg("Proxy refused the tunnel")),
+    }
+
+    let (user, pass) = match &proxy.credentials {
+        Some(credentials) => credentials,
+        None => return Err(ErrorKind::ProxyUnauthorized.new()),
+    };
+    let challenge = header_values(&head.head, "proxy-authenticate")
+        .find_map(|v| strip_prefix_ignore_case(v, "digest "))
+        .ok_or_else(|| ErrorKind::ProxyUnauthorized.new())?;
+    let digest = digest_auth(user, pass, target, challenge)
+        .ok_or_else(|| ErrorKind::ProxyUnauthorized.msg("Unsupported Digest challenge"))?;
+
+    // the 407 has to be read off the connection to use it again
+    match head.content_length {
+        Some(len) if !head.close => {
+            io::copy(&mut Read::by_ref(stream).take(len), &mut io::sink())?;
+        }
+        _ => *stream = reconnect()?,
+    }
+    let head = send_connect(stream, target, user_agent, Some(&digest))?;
+    match head.status {
+        200..=299 => Ok(()),
+        407 => Err(ErrorKind::ProxyUnauthorized.new()),
+        _ => Err(ErrorKind::ProxyConnect.msg("Proxy refused the tunnel")),
+    }
+}
+
+// The status line and headers of a proxy's answer to CONNECT.
+struct ProxyHead {
+    status: u16,
+    head: String,
+    content_length: Option<u64>,
+    // the proxy closes the connection after the answer
+    close: bool,
+}
+
+fn header_values<'a>(head: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    head.split("\r\n").skip(1).filter_map(move |line| {
+        let (n, v) = line.split_once(':')?;
+        n.trim().eq_ignore_ascii_case(name).then(|| v.trim())
+    })
+}
+
+fn send_connect(
+    stream: &mut TcpStream,
+    target: &str,
+    user_agent: Option<&str>,
+    authorization: Option<&str>,
+) -> Result<ProxyHead, Error> {
+    let mut connect = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target);
+    if let Some(user_agent) = user_agent {
+        connect.push_str(&format!("User-Agent: {}\r\n", user_agent));
+    }
+    if let Some(authorization) = authorization {
+        connect.push_str(&format!("Proxy-Authorization: {}\r\n", authorization));
+    }
+    connect.push_str("\r\n");
+    stream.write_all(connect.as_bytes())?;
+
+    // byte by byte, what follows the head belongs to the tunnel
+    let mut head = Vec::with_capacity(128);
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() == 8192 {
+            return Err(ErrorKind::ProxyConnect.msg("Proxy response head too long"));
+        }
+        if stream.read(&mut byte)? == 0 {
+            return Err(ErrorKind::ProxyConnect.msg("Proxy closed the connection"));
+        }
+        head.push(byte[0]);
+    }
+
+    // HTTP/1.1 200 Connection established
+    let status = head
+        .get(9..12)
+        .filter(|_| head.starts_with(b"HTTP/1."))
+        .and_then(|s| std::str::from_utf8(s).ok())
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| ErrorKind::ProxyConnect.msg("Malformed proxy response"))?;
+    let http10 = head.starts_with(b"HTTP/1.0");
+    let head = String::from_utf8_lossy(&head).into_owned();
+    let content_length = match header_values(&head, "transfer-encoding").next() {
+        Some(_) => None,
+        None => header_values(&head, "content-length")
+            .next()
+            .and_then(|v| v.parse().ok()),
+    };
+    let connection = header_values(&head, "connection").next().unwrap_or("");
+    let has = |token: &str| {
+        connection
+            .split(',')
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    };
+    let close = has("close") || (http10 && !has("keep-alive"));
+    Ok(ProxyHead {
+        status,
+        head,
+        content_length,
+        close,
+    })
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    match s.get(..prefix.len()) {
+        Some(p) if p.eq_ignore_ascii_case(prefix) => Some(&s[prefix.len()..]),
+        _ => None,
+    }
+}
+
+// The Proxy-Authorization for a Digest `challenge`, RFC 7616, with MD5.
+// `None` for other algorithms and protections than `auth`.
+fn digest_auth(user: &str, pass: &str, uri: &str, challenge: &str) -> Option<String> {
+    let params = auth_params(challenge);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    };
+    let realm = param("realm").unwrap_or("");
+    let nonce = param("nonce")?;
+    let sess = match param("algorithm") {
+        None => false,
+        Some(a) if a.eq_ignore_ascii_case("MD5") => false,
+        Some(a) if a.eq_ignore_ascii_case("MD5-sess") => true,
+        Some(_) => return None,
+    };
+    let qop = match param("qop") {
+        Some(qop) => {
+            if !qop.split(',').any(|q| q.trim() == "auth") {
+                return None;
+            }
+            Some("auth")
+        }
+        None => None,
+    };
+
+    let cnonce = format!("{:016x}", RandomState::new().build_hasher().finish());
+    let mut ha1 = md5_hex(format!("{}:{}:{}", user, realm, pass).as_bytes());
+    if sess {
+        ha1 = md5_hex(format!("{}:{}:{}", ha1, nonce, cnonce).as_bytes());
+    }
+    let ha2 = md5_hex(format!("CONNECT:{}", uri).as_bytes());
+    let response = match qop {
+        Some(qop) => format!("{}:{}:00000001:{}:{}:{}", ha1, nonce, cnonce, qop, ha2),
+        None => format!("{}:{}:{}", ha1, nonce, ha2),
+    };
+
+    let mut out = format!(
+        "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{}\"",
+        user,
+        realm,
+        nonce,
+        uri,
+        md5_hex(response.as_bytes())
+    );
+    if let Some(algorithm) = param("algorithm") {
+        out.push_str(&format!(", algorithm={}", algorithm));
+    }
+    if let Some(opaque) = param("opaque") {
+        out.push_str(&format!(", opaque=\"{}\"", opaque));
+    }
+    if let Some(qop) = qop {
+        out.push_str(&format!(
+            ", qop={}, nc=00000001, cnonce=\"{}\"",
+            qop, cnonce
+        ));
+    }
+    Some(out)
+}
+
+// `realm="a, b", nonce=xyz` into its names and unquoted values.
+fn auth_params(s: &str) -> Vec<(String, String)> {
+    let mut params = vec![];
+    let mut rest = s.trim_start();
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim().trim_start_matches(',').trim().to_string();
+        rest = rest[eq + 1..].trim_start();
+        let mut value = String::new();
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut chars = quoted.char_indices();
+            rest = "";
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        rest = &quoted[i + 1..];
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            value.push_str(rest[..end].trim());
+            rest = &rest[end..];
+        }
+        params.push((name, value));
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+    params
+}
+
+fn md5_hex(data: &[u8]) -> String {
+    md5(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// MD5, RFC 1321. Only for Digest authentication, which requires it.
+fn md5(data: &[u8]) -> [u8; 16] {
+    const S: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5,
+        9, 14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10,
+        15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    // floor(abs(sin(i + 1)) * 2^32)
+    let k: Vec<u32> = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32)
+        .collect();
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut h: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in msg.chunks(64) {
+        let m: Vec<u32> = block
+            .chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = h;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(k[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(S[i]));
+        }
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+    }
+
+    let mut out = [0; 16];
+    for (i, word) in h.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
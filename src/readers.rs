@@ -1,20 +1,165 @@
+use crate::header::Headers;
 use crate::stream::Stream;
 use crate::response::Buffer;
-use std::io::{self, Read};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-type CarryOver = Buffer<16_384>;
+type CarryOver = Buffer;
+
+/// Told how many bytes of a body were transferred so far, and the total
+/// if it's known.
+pub(crate) type ProgressFn = dyn Fn(u64, Option<u64>) + Send + Sync;
+
+/// Keeps a transfer at or under a rate in bytes per second, on average
+/// since it started.
+pub(crate) struct Throttle {
+    rate: u64,
+    start: Option<Instant>,
+    done: u64,
+}
+
+impl Throttle {
+    pub fn new(rate: u64) -> Self {
+        Throttle {
+            rate: rate.max(1),
+            start: None,
+            done: 0,
+        }
+    }
+
+    /// How much of `want` bytes to transfer next, at most a tenth of a
+    /// second's worth to keep it smooth.
+    pub fn limit(&self, want: usize) -> usize {
+        want.min((self.rate / 10).max(1) as usize)
+    }
+
+    /// Count `n` bytes transferred, sleeping until the average is back
+    /// down to the rate.
+    pub fn record(&mut self, n: usize) {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.done += n as u64;
+        let due = Duration::from_secs_f64(self.done as f64 / self.rate as f64);
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+pub(crate) struct ThrottledReader<R> {
+    inner: R,
+    throttle: Throttle,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    pub fn new(inner: R, rate: u64) -> Self {
+        ThrottledReader {
+            inner,
+            throttle: Throttle::new(rate),
+        }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.throttle.limit(buf.len());
+        let n = self.inner.read(&mut buf[..len])?;
+        self.throttle.record(n);
+        Ok(n)
+    }
+}
+
+/// Copies the bytes read through it to a writer, see
+/// [`Response::tee()`](crate::Response::tee).
+///
+/// A failed write is returned as the error of the read.
+pub struct TeeReader<R, W> {
+    inner: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> TeeReader<R, W> {
+    pub fn new(inner: R, writer: W) -> Self {
+        TeeReader { inner, writer }
+    }
+
+    /// The writer, e.g. to get at a `Vec` written to.
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.writer.write_all(&buf[..n])?;
+        if n == 0 {
+            self.writer.flush()?;
+        }
+        Ok(n)
+    }
+}
+
+/// Reports the bytes read through it to a progress callback.
+pub(crate) struct ProgressReader<R> {
+    inner: R,
+    done: u64,
+    total: Option<u64>,
+    progress: Arc<ProgressFn>,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub fn new(inner: R, total: Option<u64>, progress: Arc<ProgressFn>) -> Self {
+        ProgressReader {
+            inner,
+            done: 0,
+            total,
+            progress,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.done += n as u64;
+            (self.progress)(self.done, self.total);
+        }
+        Ok(n)
+    }
+}
 
 pub(crate) struct ComboReader {
     pub co: CarryOver,
+    // read position in co.buf, starts after the header terminator
+    pub pos: usize,
     pub st: Stream,
 }
 
+impl ComboReader {
+    fn carry_end(&self) -> usize {
+        self.co.head_len + 2 + self.co.carry_len
+    }
+
+    /// The stream, unless bytes the server sent are left in the carry over.
+    pub fn into_stream(self) -> Option<Stream> {
+        if self.pos < self.carry_end() {
+            None
+        } else {
+            Some(self.st)
+        }
+    }
+}
+
 impl Read for ComboReader {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.co.head_len < self.co.carry_len {
-            let mut b = &self.co.buf[self.co.head_len..self.co.head_len+self.co.carry_len];
-            let c = b.read(buf)?;
-            self.co.head_len += c;
+        let end = self.carry_end();
+        if self.pos < end {
+            let c = (&self.co.buf[self.pos..end]).read(buf)?;
+            self.pos += c;
             Ok(c)
         } else {
             self.st.read(buf)
@@ -22,9 +167,142 @@ impl Read for ComboReader {
     }
 }
 
+/// Decodes a `Transfer-Encoding: chunked` body, keeping the trailer
+/// headers that may follow the last chunk.
+pub(crate) struct ChunkDecoder<R> {
+    source: R,
+    // left of the chunk being read, None between chunks
+    remaining: Option<usize>,
+    done: bool,
+    trailers: Option<Box<Headers>>,
+}
+
+impl<R: Read> ChunkDecoder<R> {
+    pub fn new(source: R) -> Self {
+        ChunkDecoder {
+            source,
+            remaining: None,
+            done: false,
+            trailers: None,
+        }
+    }
+
+    /// Whether the last chunk and the trailers were read.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.source
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    pub fn take_trailers(&mut self) -> Option<Box<Headers>> {
+        self.trailers.take()
+    }
+
+    // A line without its CRLF, byte by byte to not read past it.
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let mut byte = [0];
+        loop {
+            if self.source.read(&mut byte)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "chunked body ended early",
+                ));
+            }
+            if byte[0] == b'\n' {
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(line);
+            }
+            if line.len() == 8192 {
+                return Err(invalid_chunk("chunk line too long"));
+            }
+            line.push(byte[0]);
+        }
+    }
+
+    // 1a;ext=value, extensions are ignored
+    fn read_chunk_size(&mut self) -> io::Result<usize> {
+        let line = self.read_line()?;
+        let size = line.split(|b| *b == b';').next().unwrap_or(&[]);
+        std::str::from_utf8(size)
+            .ok()
+            .and_then(|s| usize::from_str_radix(s.trim(), 16).ok())
+            .ok_or_else(|| invalid_chunk("malformed chunk size"))
+    }
+
+    fn read_trailers(&mut self) -> io::Result<()> {
+        let mut raw = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            raw.extend_from_slice(&line);
+            raw.extend_from_slice(b"\r\n");
+        }
+        if !raw.is_empty() {
+            let trailers = Headers::try_from(&raw[..])
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.trailers = Some(Box::new(trailers));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ChunkDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        let remaining = match self.remaining {
+            Some(n) => n,
+            None => {
+                let size = self.read_chunk_size()?;
+                if size == 0 {
+                    self.read_trailers()?;
+                    self.done = true;
+                    return Ok(0);
+                }
+                size
+            }
+        };
+
+        let max = buf.len().min(remaining);
+        let n = self.source.read(&mut buf[..max])?;
+        if n == 0 && max > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "chunked body ended early",
+            ));
+        }
+        if n == remaining {
+            if !self.read_line()?.is_empty() {
+                return Err(invalid_chunk("chunk longer than its size"));
+            }
+            self.remaining = None;
+        } else {
+            self.remaining = Some(remaining - n);
+        }
+        Ok(n)
+    }
+}
+
+fn invalid_chunk(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
 // ErrorReader returns an error for every read.
 // The error is as close to a clone of the underlying
 // io::Error as we can get.
+#[allow(dead_code)]
 pub(crate) struct ErrorReader(io::Error);
 
 impl Read for ErrorReader {
@@ -33,9 +311,7 @@ impl Read for ErrorReader {
     }
 }
 
-/**
- * Iterators to emulate control loops for Read
- */
+// Iterators to emulate control loops for Read
 
 pub struct ReadIterator<'a, R> {
     r: &'a mut R,
@@ -1,21 +1,857 @@
+use std::error::Error as _;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
 use crate::url::Url;
 
-use crate::response::{Response};
-use crate::unit::{connect, send_request};
-use crate::agent::Agent;
-use crate::error::{Error};
+use crate::agent::{Agent, RedirectPolicy};
+use crate::body::{send_body, BodySize, Payload, SizedReader};
+use crate::cache;
+use crate::cancel::CancelToken;
+use crate::error::{Error, ErrorKind};
+use crate::header::{valid_name, valid_value};
+use crate::middleware::MiddlewareNext;
+use crate::multipart::MultipartBuilder;
+use crate::pool::{keep_alive_hints, PoolKey, PoolReturn};
+use crate::readers::{ProgressFn, ProgressReader, ThrottledReader};
+use crate::response::Response;
+use crate::retry::{is_idempotent, RetryPolicy};
+use crate::stream::Stream;
+use crate::timings::Timings;
+use crate::unit::{basic_auth, connect, connect_new, send_request};
 
 /// Request instances are builders that creates a request.
-pub struct Request;
+///
+/// ```no_run
+/// let url = ureq::Url::parse("http://example.com/")?;
+/// let resp = ureq::get(url)
+///     .set("X-Api-Key", "foobar")
+///     .call()?;
+/// # Ok::<(), ureq::Error>(())
+/// ```
+pub struct Request {
+    pub(crate) agent: Agent,
+    pub(crate) method: String,
+    pub(crate) url: Url,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) redirect_policy: RedirectPolicy,
+    pub(crate) timeout_connect: Option<Duration>,
+    pub(crate) timeout_read: Option<Duration>,
+    pub(crate) timeout_write: Option<Duration>,
+    pub(crate) retry_policy: Option<RetryPolicy>,
+    pub(crate) connect_to: Option<SocketAddr>,
+    pub(crate) upload_progress: Option<Arc<ProgressFn>>,
+    pub(crate) download_progress: Option<Arc<ProgressFn>>,
+    pub(crate) rate_limit: Option<u64>,
+    pub(crate) max_body_size: Option<u64>,
+    pub(crate) cancel: CancelToken,
+    #[cfg(feature = "gzip")]
+    pub(crate) gzip_body: bool,
+}
+
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Request({} {}, {} headers)",
+            self.method,
+            self.url.without_userinfo(),
+            self.headers.len()
+        )
+    }
+}
 
 impl Request {
-    pub fn call(agent: &Agent, url: &Url) -> Result<Response, Error> {
-        connect(agent, url)
-            .and_then(|mut stream| {
-                send_request(url.host_str(), url.path(), agent.user_agent, &mut stream)
-                    .map(|_| stream)
-                    .map_err(|e| e.into())
-            })
-            .and_then(Response::do_from_stream)
+    pub(crate) fn new(agent: Agent, method: &str, url: Url) -> Request {
+        let config = &agent.config;
+        let headers = match &config.bearer_token {
+            Some(token) => vec![("Authorization".to_string(), bearer(token))],
+            None => vec![],
+        };
+        Request {
+            method: method.to_string(),
+            url,
+            headers,
+            redirect_policy: config.redirect_policy.clone(),
+            timeout_connect: config.timeout_connect,
+            timeout_read: config.timeout_read,
+            timeout_write: config.timeout_write,
+            retry_policy: config.retry_policy.clone(),
+            connect_to: None,
+            upload_progress: None,
+            download_progress: None,
+            rate_limit: config.rate_limit,
+            max_body_size: None,
+            cancel: CancelToken::new(),
+            #[cfg(feature = "gzip")]
+            gzip_body: false,
+            agent,
+        }
+    }
+
+    /// Set a header, replacing any earlier value of the same name.
+    ///
+    /// Names are case insensitive. Invalid names or values are reported
+    /// as [`ErrorKind::BadHeader`](crate::ErrorKind::BadHeader) by [`call()`](Request::call).
+    ///
+    /// `Host` and `User-Agent` replace the ones ureq sends by default.
+    pub fn set(mut self, name: &str, value: &str) -> Self {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Authenticate with HTTP Basic authentication, setting the
+    /// `Authorization` header to the base64 encoded `user:password`.
+    ///
+    /// Like any `Authorization` header, it's dropped on a redirect to
+    /// another host.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/private")?;
+    /// let resp = ureq::get(url).auth_basic("aladdin", "open sesame").call()?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn auth_basic(self, user: &str, password: &str) -> Self {
+        let credentials = basic_auth(user, password);
+        self.set("Authorization", &credentials)
+    }
+
+    /// Authenticate with a bearer token, e.g. an OAuth 2.0 access token,
+    /// setting the `Authorization` header to `Bearer <token>`.
+    ///
+    /// Like any `Authorization` header, it's dropped on a redirect to
+    /// another host.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("https://api.example.com/me")?;
+    /// let resp = ureq::get(url).auth_bearer("mF_9.B5f-4.1JqM").call()?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn auth_bearer(self, token: &str) -> Self {
+        self.set("Authorization", &bearer(token))
+    }
+
+    /// Ask for part of the body, from byte `start` up to and including
+    /// byte `end`, or to the end of the body for `None`. Sets the `Range`
+    /// header.
+    ///
+    /// A server that supports ranges answers `206 Partial Content`, one
+    /// that doesn't sends the whole body with `200 OK`.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/big.iso")?;
+    /// // the first kilobyte
+    /// let resp = ureq::get(url).range(0, Some(1023)).call()?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn range(self, start: u64, end: Option<u64>) -> Self {
+        let range = match end {
+            Some(end) => format!("bytes={}-{}", start, end),
+            None => format!("bytes={}-", start),
+        };
+        self.set("Range", &range)
+    }
+
+    /// The value of a header set on this request.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Follow up to `n` redirects, shorthand for [`RedirectPolicy::Limited`].
+    ///
+    /// `0` is the same as [`RedirectPolicy::None`].
+    pub fn redirects(self, n: u32) -> Self {
+        let policy = match n {
+            0 => RedirectPolicy::None,
+            n => RedirectPolicy::Limited(n),
+        };
+        self.redirect_policy(policy)
+    }
+
+    /// Decide which redirects to follow, `Limited(5)` unless changed on the agent.
+    pub fn redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect_policy = policy;
+        self
+    }
+
+    /// Limit connecting, each read and each write to `timeout`, overriding
+    /// the agent's timeouts for this request.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// let agent = ureq::AgentBuilder::new()
+    ///     .timeout_read(Some(Duration::from_secs(2)))
+    ///     .build();
+    /// let url = ureq::Url::parse("http://example.com/export")?;
+    /// let resp = agent.get(url).timeout(Duration::from_secs(120)).call()?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout_connect = Some(timeout);
+        self.timeout_read = Some(timeout);
+        self.timeout_write = Some(timeout);
+        self
+    }
+
+    /// Limit for establishing the TCP connection, overriding the agent's.
+    /// Not used when a pooled connection is reused.
+    pub fn timeout_connect(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout_connect = timeout;
+        self
+    }
+
+    /// Limit for each read from the socket, overriding the agent's.
+    ///
+    /// A read that takes longer is an [`ErrorKind::Timeout`](crate::ErrorKind::Timeout)
+    /// error, or an [`io::ErrorKind::TimedOut`](std::io::ErrorKind::TimedOut)
+    /// one when reading the body. `None` waits forever. The duration must
+    /// not be zero.
+    pub fn timeout_read(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout_read = timeout;
+        self
+    }
+
+    /// Limit for each write to the socket, overriding the agent's.
+    ///
+    /// A write that takes longer is an [`ErrorKind::Timeout`](crate::ErrorKind::Timeout)
+    /// error. `None` waits forever. The duration must not be zero.
+    pub fn timeout_write(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout_write = timeout;
+        self
+    }
+
+    /// Limit sending the body and reading the response body to `rate`
+    /// bytes per second, overriding the agent's. `None` doesn't limit.
+    /// The rate must not be zero.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/big.iso")?;
+    /// // 100 KB/s, to leave room for the user's own traffic
+    /// let resp = ureq::get(url).rate_limit(Some(100_000)).call()?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn rate_limit(mut self, rate: Option<u64>) -> Self {
+        self.rate_limit = rate;
+        self
+    }
+
+    /// Fail reading a response body larger than `bytes`, to not run out
+    /// of memory on a server sending without end.
+    ///
+    /// A read of the [`ResponseReader`](crate::ResponseReader) returns an
+    /// [`io::ErrorKind::InvalidData`](std::io::ErrorKind::InvalidData)
+    /// error once the limit is passed, or right away for a
+    /// `Content-Length` over it.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/")?;
+    /// let resp = ureq::get(url).max_body_size(1024 * 1024).call()?;
+    /// let body = resp.into_string()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn max_body_size(mut self, bytes: u64) -> Self {
+        self.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Compress the body with gzip and send it with
+    /// `Content-Encoding: gzip`, for servers that accept it. Bodies sent
+    /// from a reader are compressed as they are sent, in chunks.
+    ///
+    /// A `Content-Length` set on the request is of the uncompressed body
+    /// and is dropped.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/ingest")?;
+    /// let logs = std::fs::read_to_string("app.log")?;
+    /// let resp = ureq::post(url).gzip_body(true).send_string(&logs)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[cfg(feature = "gzip")]
+    pub fn gzip_body(mut self, gzip: bool) -> Self {
+        self.gzip_body = gzip;
+        self
+    }
+
+    /// A token to cancel this request with from another thread, see
+    /// [`CancelToken`].
+    ///
+    /// ```no_run
+    /// use std::{thread, time::Duration};
+    ///
+    /// let url = ureq::Url::parse("http://example.com/slow")?;
+    /// let req = ureq::get(url);
+    /// let token = req.cancel_token();
+    /// thread::spawn(move || {
+    ///     thread::sleep(Duration::from_secs(1));
+    ///     token.cancel();
+    /// });
+    /// let result = req.call();
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Retry failed attempts as described by `policy`, see [`RetryPolicy`].
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Connect to `addr` rather than to what the url's host resolves to,
+    /// like curl's `--connect-to`. The host is still used for the `Host`
+    /// header and to check the server's certificate, e.g. to test one
+    /// server behind a load balancer.
+    ///
+    /// Redirects to another host, scheme or port connect as usual. With a
+    /// proxy, the tunnel is to `addr`.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("https://example.com/health")?;
+    /// let resp = ureq::get(url)
+    ///     .connect_to("10.0.0.12:443".parse().unwrap())
+    ///     .call()?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn connect_to(mut self, addr: SocketAddr) -> Self {
+        self.connect_to = Some(addr);
+        self
+    }
+
+    /// Call `progress` as the body is sent, with the bytes sent so far and
+    /// the size of the body, if known. A retried request starts again
+    /// from `0`.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/upload")?;
+    /// let file = std::fs::File::open("data.bin")?;
+    /// let len = file.metadata()?.len();
+    /// let resp = ureq::put(url)
+    ///     .set("Content-Length", &len.to_string())
+    ///     .upload_progress(|sent, total| eprintln!("{}/{:?}", sent, total))
+    ///     .send(file)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn upload_progress(
+        mut self,
+        progress: impl Fn(u64, Option<u64>) + Send + Sync + 'static,
+    ) -> Self {
+        self.upload_progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Call `progress` as the response body is read, with the bytes read
+    /// so far and the length of the body, if known from `Content-Length`.
+    ///
+    /// ```no_run
+    /// use std::io::Read;
+    ///
+    /// let url = ureq::Url::parse("http://example.com/big.iso")?;
+    /// let resp = ureq::get(url)
+    ///     .download_progress(|read, total| eprintln!("{}/{:?}", read, total))
+    ///     .call()?;
+    /// let mut body = Vec::new();
+    /// Read::read_to_end(&mut resp.into_reader(), &mut body)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn download_progress(
+        mut self,
+        progress: impl Fn(u64, Option<u64>) + Send + Sync + 'static,
+    ) -> Self {
+        self.download_progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Add the fields of `query` to the query string, form-encoded, after
+    /// any pairs the url has already.
+    ///
+    /// Requires feature `ureq = { version = "*", features = ["json"] }`
+    ///
+    /// A `None` field is left out. Nested structs, maps and sequences
+    /// can't be encoded, they are an [`ErrorKind::InvalidUrl`](crate::ErrorKind::InvalidUrl) error.
+    ///
+    /// ```
+    /// #[derive(serde::Serialize)]
+    /// struct Search {
+    ///     q: &'static str,
+    ///     page: u32,
+    ///     lang: Option<&'static str>,
+    /// }
+    ///
+    /// let url = ureq::Url::parse("http://example.com/search?v=2")?;
+    /// let req = ureq::get(url).query_struct(Search {
+    ///     q: "rust & http",
+    ///     page: 2,
+    ///     lang: None,
+    /// })?;
+    /// assert_eq!(req.url().query(), Some("v=2&q=rust+%26+http&page=2"));
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn query_struct(mut self, query: impl serde::Serialize) -> Result<Self, Error> {
+        let pairs = serde_urlencoded::to_string(query).map_err(|e| {
+            ErrorKind::InvalidUrl
+                .msg("Failed to serialize query")
+                .src(e)
+        })?;
+        if pairs.is_empty() {
+            return Ok(self);
+        }
+        let query = match self.url.query() {
+            Some(q) if !q.is_empty() => format!("{}&{}", q, pairs),
+            _ => pairs,
+        };
+        self.url.set_query(Some(&query))?;
+        Ok(self)
+    }
+
+    /// The request method, e.g. `GET`.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// The url this request is sent to.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Send the request without a body.
+    pub fn call(self) -> Result<Response, Error> {
+        self.do_call(Payload::Empty)
+    }
+
+    /// Send data from a reader.
+    ///
+    /// The body is sent with `Transfer-Encoding: chunked` unless a
+    /// `Content-Length` header was set, in which case the reader must yield
+    /// exactly that many bytes.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/upload")?;
+    /// let file = std::fs::File::open("data.bin")?;
+    /// let resp = ureq::put(url).send(file)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn send(self, reader: impl Read) -> Result<Response, Error> {
+        self.do_call(Payload::Reader(Box::new(reader)))
+    }
+
+    /// Send a `multipart/form-data` body, streaming file parts as they are sent.
+    ///
+    /// The `Content-Type` header is set to the one of the form.
+    pub fn send_multipart(self, form: MultipartBuilder) -> Result<Response, Error> {
+        let content_type = form.content_type();
+        self.set("Content-Type", &content_type)
+            .send(form.into_reader())
+    }
+
+    /// Send data from a byte slice.
+    ///
+    /// The `Content-Length` header is set to the length of the slice.
+    pub fn send_bytes(self, data: &[u8]) -> Result<Response, Error> {
+        self.do_call(Payload::Bytes(data))
+    }
+
+    /// Send data as a string, setting `Content-Type: text/plain; charset=utf-8`
+    /// unless another content type was set.
+    pub fn send_string(mut self, data: &str) -> Result<Response, Error> {
+        if self.header("Content-Type").is_none() {
+            self = self.set("Content-Type", "text/plain; charset=utf-8");
+        }
+        self.do_call(Payload::Text(data))
+    }
+
+    /// Send data as JSON, setting `Content-Type: application/json` unless
+    /// another content type was set.
+    ///
+    /// Requires the `json` feature.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/post")?;
+    /// let resp = ureq::post(url)
+    ///     .send_json(serde_json::json!({ "name": "martin", "rust": true }))?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn send_json(mut self, data: impl serde::Serialize) -> Result<Response, Error> {
+        if self.header("Content-Type").is_none() {
+            self = self.set("Content-Type", "application/json");
+        }
+        let json = serde_json::to_vec(&data)
+            .map_err(|e| ErrorKind::Json.msg("Failed to serialize JSON").src(e))?;
+        self.do_call(Payload::Bytes(&json))
+    }
+
+    fn do_call(self, payload: Payload) -> Result<Response, Error> {
+        let agent = self.agent.clone();
+        let resp = if agent.config.middleware.is_empty() {
+            self.send_following(payload)?
+        } else {
+            let mut chain = agent.config.middleware.iter().map(|m| m.as_ref());
+            let next = MiddlewareNext {
+                chain: &mut chain,
+                send: Box::new(move |req: Request| req.send_following(payload)),
+            };
+            next.handle(self)?
+        };
+        // middleware sees error statuses as responses, callers as errors
+        match resp.status_code() {
+            code @ 400..=599 => Err(Error::Status(code, Box::new(resp))),
+            _ => Ok(resp),
+        }
+    }
+
+    // Sends the request, following redirects.
+    fn send_following(mut self, payload: Payload) -> Result<Response, Error> {
+        #[cfg(feature = "gzip")]
+        let mut compressed = vec![];
+        #[cfg(feature = "gzip")]
+        let payload = match (self.gzip_body, payload) {
+            (false, payload) | (true, payload @ Payload::Empty) => payload,
+            (true, payload) => {
+                self.headers
+                    .retain(|(n, _)| !n.eq_ignore_ascii_case("content-length"));
+                self = self.set("Content-Encoding", "gzip");
+                payload.gzip(&mut compressed)?
+            }
+        };
+        if !valid_name(&self.method) {
+            return Err(ErrorKind::InvalidMethod.msg("Invalid method"));
+        }
+        for (name, value) in &self.headers {
+            if !valid_name(name) {
+                return Err(ErrorKind::BadHeader.msg("Invalid header name"));
+            }
+            if !valid_value(value) {
+                return Err(ErrorKind::BadHeader.msg("Invalid header value"));
+            }
+        }
+        let mut content_length = match self.header("Content-Length") {
+            Some(len) => Some(
+                len.parse::<u64>()
+                    .map_err(|_| ErrorKind::BadHeader.msg("Invalid Content-Length"))?,
+            ),
+            None => None,
+        };
+
+        let mut payload = payload;
+        let mut hops = 0;
+        let mut refreshed = false;
+        loop {
+            let had_body = content_length.is_some() || !matches!(payload, Payload::Empty);
+            let replay = payload.try_clone();
+            let result = match self.agent.config.cache.clone() {
+                Some(cache) if self.method == "GET" => cache::send(&mut self, &cache, |req| {
+                    req.send_retrying(payload, content_length)
+                }),
+                _ => self.send_retrying(payload, content_length),
+            };
+            let mut resp = result.map_err(|e| e.with_url(&self.url))?;
+            resp.url = Some(self.url.without_userinfo());
+            resp.progress = self.download_progress.clone();
+            resp.rate_limit = self.rate_limit;
+            resp.max_body_size = self.max_body_size;
+            resp.redact_debug = self.agent.config.redact_debug;
+
+            let code = resp.status_code();
+            // new credentials get one more attempt
+            let refresh = self.agent.config.on_unauthorized.clone();
+            let again = replay.as_ref().and_then(|p| p.try_clone());
+            if let (401, false, Some(refresh), Some(again)) = (code, refreshed, refresh, again) {
+                if let Some(auth) = refresh(&resp) {
+                    refreshed = true;
+                    self = self.set("Authorization", &auth);
+                    payload = again;
+                    continue;
+                }
+            }
+            if !matches!(code, 301 | 302 | 303 | 307 | 308)
+                || matches!(self.redirect_policy, RedirectPolicy::None)
+            {
+                return Ok(resp);
+            }
+            let location = match resp.header("location") {
+                Some(l) => l,
+                None => return Ok(resp),
+            };
+            let next = self.url.join(location).map_err(|e| {
+                let e = ErrorKind::BadHeader.msg("Bad redirect location").src(e);
+                e.with_url(&self.url)
+            })?;
+
+            match &self.redirect_policy {
+                RedirectPolicy::Limited(max) if hops == *max => {
+                    return Err(ErrorKind::TooManyRedirects.new().with_url(&self.url));
+                }
+                RedirectPolicy::Custom(follow) if !follow(&self.url, &next) => {
+                    return Ok(resp);
+                }
+                _ => {}
+            }
+            hops += 1;
+
+            // 307 and 308 send the same request again, body and all
+            let resend = match (code, replay) {
+                (307 | 308, Some(replay)) if had_body => Some(replay),
+                (307 | 308, None) => {
+                    let e =
+                        ErrorKind::BodyNotReplayable.msg("Can't resend a body read from a reader");
+                    return Err(e.with_url(&self.url));
+                }
+                _ => None,
+            };
+            if matches!(code, 301..=303) {
+                if self.method != "HEAD" {
+                    self.method = "GET".to_string();
+                }
+                self.headers.retain(|(n, _)| {
+                    !n.eq_ignore_ascii_case("content-length")
+                        && !n.eq_ignore_ascii_case("content-type")
+                });
+            }
+
+            // credentials are for the original host only, the address for
+            // the original scheme and port as well
+            let same_host = next.host_str().eq_ignore_ascii_case(self.url.host_str());
+            if !same_host {
+                self.headers
+                    .retain(|(n, _)| !n.eq_ignore_ascii_case("authorization"));
+            }
+            if !same_host || next.scheme() != self.url.scheme() || next.port() != self.url.port() {
+                self.connect_to = None;
+            }
+            self.url = next;
+            match resend {
+                Some(replay) => payload = replay,
+                None => {
+                    payload = Payload::Empty;
+                    content_length = None;
+                }
+            }
+        }
+    }
+
+    // Where to connect instead of resolving the host, if anywhere.
+    pub(crate) fn connect_addr(&self) -> Option<SocketAddr> {
+        if self.connect_to.is_some() {
+            return self.connect_to;
+        }
+        let (host, port) = (self.url.host_str(), self.url.port());
+        self.agent
+            .config
+            .resolve_overrides
+            .iter()
+            .find(|(h, p, _)| h.eq_ignore_ascii_case(host) && *p == port)
+            .map(|(_, _, addr)| *addr)
+    }
+
+    pub(crate) fn send_retrying(
+        &self,
+        payload: Payload,
+        content_length: Option<u64>,
+    ) -> Result<Response, Error> {
+        let sized = |payload| sized_body(payload, content_length);
+        // set before a cache reads the body into memory
+        let send_once = |body| {
+            let mut resp = self.send_once(body)?;
+            resp.max_body_size = self.max_body_size;
+            Ok(resp)
+        };
+        let policy = match &self.retry_policy {
+            Some(policy) if is_idempotent(&self.method) => policy,
+            _ => return send_once(sized(payload)),
+        };
+
+        let mut payload = payload;
+        let mut attempt = 1;
+        loop {
+            let again = payload.try_clone();
+            let result = send_once(sized(payload));
+            let (wait, again) = match (policy.wait(attempt, &result), again) {
+                (Some(wait), Some(again)) => (wait, again),
+                _ => return result,
+            };
+            drop(result);
+            thread::sleep(wait);
+            attempt += 1;
+            payload = again;
+        }
+    }
+
+    // The body as sent, throttled and reporting progress if asked to.
+    fn wrap_body<'a>(&self, mut body: SizedReader<'a>) -> SizedReader<'a> {
+        if let Some(rate) = self.rate_limit {
+            body.reader = Box::new(ThrottledReader::new(body.reader, rate));
+        }
+        if let Some(progress) = &self.upload_progress {
+            let total = match body.size {
+                BodySize::Empty => Some(0),
+                BodySize::Known(len) => Some(len),
+                BodySize::Unknown => None,
+            };
+            body.reader = Box::new(ProgressReader::new(body.reader, total, progress.clone()));
+        }
+        body
+    }
+
+    fn send_once(&self, body: SizedReader) -> Result<Response, Error> {
+        let body = self.wrap_body(body);
+        #[cfg(feature = "testing")]
+        if let Some(mock) = &self.agent.config.mock {
+            let resp = mock.call(self, body)?;
+            #[cfg(feature = "cookies")]
+            self.save_cookies(&resp);
+            return Ok(resp);
+        }
+        #[cfg(feature = "vcr")]
+        if let Some(cassette) = crate::vcr::cassette() {
+            let mut resp = cassette.call(self, body)?;
+            resp.is_head = self.method == "HEAD";
+            #[cfg(feature = "cookies")]
+            self.save_cookies(&resp);
+            return Ok(resp);
+        }
+        self.cancel.check()?;
+        let mut timings = Timings::new();
+        let (stream, mut created, mut reused) = connect(self, &mut timings)?;
+        #[cfg(feature = "http2")]
+        if stream.is_h2() {
+            if let Some(socket) = stream.socket() {
+                self.cancel.watch(socket)?;
+            }
+            let mut resp = match crate::h2::send(self, body, stream, timings) {
+                Ok(resp) => resp,
+                Err(_) if self.cancel.is_cancelled() => {
+                    return Err(ErrorKind::Cancelled.msg("Request was cancelled"))
+                }
+                Err(e) => {
+                    self.cancel.release();
+                    return Err(e);
+                }
+            };
+            resp.is_head = self.method == "HEAD";
+            resp.connection_reused = reused;
+            #[cfg(feature = "cookies")]
+            self.save_cookies(&resp);
+            return Ok(resp);
+        }
+        if let Some(socket) = stream.socket() {
+            self.cancel.watch(socket)?;
+        }
+        // the server may have closed a pooled connection while it was idle,
+        // which shows when using it. An idempotent request with the body in
+        // memory is sent once more on a new connection.
+        let replay = match body.bytes {
+            Some(bytes) if reused && is_idempotent(&self.method) => Some((body.size, bytes)),
+            _ => None,
+        };
+        let result = match (self.send_http1(body, stream, timings), replay) {
+            (Err(e), Some((size, bytes))) if is_stale(&e) && !self.cancel.is_cancelled() => {
+                self.cancel.release();
+                let mut timings = Timings::new();
+                let stream = connect_new(self, &mut timings)?;
+                created = Instant::now();
+                reused = false;
+                if let Some(socket) = stream.socket() {
+                    self.cancel.watch(socket)?;
+                }
+                let body = self.wrap_body(SizedReader {
+                    size,
+                    reader: Box::new(bytes),
+                    bytes: Some(bytes),
+                });
+                self.send_http1(body, stream, timings)
+            }
+            (result, _) => result,
+        };
+        let mut resp = match result {
+            Ok(resp) => resp,
+            // the socket was shut down under the request
+            Err(_) if self.cancel.is_cancelled() => {
+                return Err(ErrorKind::Cancelled.msg("Request was cancelled"))
+            }
+            Err(e) => {
+                self.cancel.release();
+                return Err(e);
+            }
+        };
+        resp.is_head = self.method == "HEAD";
+        resp.connection_reused = reused;
+        // a server taking no more requests on the connection closes it
+        let (server_timeout, max) = keep_alive_hints(resp.header("keep-alive"));
+        if max != Some(0) {
+            resp.pool_return = Some(PoolReturn {
+                agent: self.agent.clone(),
+                key: PoolKey::new(self),
+                created,
+                server_timeout,
+                cancel: self.cancel.clone(),
+            });
+        }
+        #[cfg(feature = "cookies")]
+        self.save_cookies(&resp);
+        Ok(resp)
+    }
+
+    fn send_http1(
+        &self,
+        body: SizedReader,
+        mut stream: Stream,
+        timings: Timings,
+    ) -> Result<Response, Error> {
+        send_request(self, &body, &mut stream)?;
+        send_body(body, &mut stream)?;
+        let config = &self.agent.config;
+        let max_head = config.max_response_header_size;
+        let lenient = config.lenient_headers;
+        let wire_log = config.wire_log.as_ref();
+        Response::do_from_stream(stream, timings, max_head, lenient, wire_log)
+    }
+
+    #[cfg(feature = "cookies")]
+    fn save_cookies(&self, resp: &Response) {
+        let set_cookies = resp.header_all("set-cookie");
+        let mut jar = self.agent.state.cookie_store.lock().unwrap();
+        jar.store_response_cookies(&self.url, set_cookies);
+    }
+}
+
+// A connection closed or reset by the server, before a response.
+fn is_stale(e: &Error) -> bool {
+    let io = match e {
+        Error::Transport(_) => e.source().and_then(|s| s.downcast_ref::<io::Error>()),
+        _ => None,
+    };
+    matches!(
+        io.map(|e| e.kind()),
+        Some(
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof
+        )
+    )
+}
+
+fn bearer(token: &str) -> String {
+    format!("Bearer {}", token)
+}
+
+// A user set Content-Length overrides the size of the payload.
+fn sized_body(payload: Payload, content_length: Option<u64>) -> SizedReader {
+    let mut body = payload.into_read();
+    if let Some(len) = content_length {
+        body.size = BodySize::Known(len);
     }
+    body
 }
@@ -0,0 +1,348 @@
+use dns_parser::RData::{A, AAAA};
+use dns_parser::{Builder, Header, Packet, QueryClass, QueryType, ResponseCode};
+use once_cell::sync::Lazy;
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use crate::error::{Error, ErrorKind};
+
+type IpAddrs = Vec<IpAddr>;
+
+/// How an agent looks up the addresses of a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolver {
+    /// The resolver of the operating system, `getaddrinfo` on unix. It
+    /// honors `/etc/hosts` and whatever else the system is configured
+    /// with. This is the default.
+    System,
+    /// ureq's own DNS client, which sends the query over UDP itself. Names
+    /// in `/etc/hosts` are looked up there first. It reads the nameservers,
+    /// search domains and the `ndots`, `timeout` and `attempts` options
+    /// from `/etc/resolv.conf`, and asks for both IPv4 and IPv6 addresses.
+    ///
+    /// Each nameserver gets `timeout` seconds to answer, 5 by default,
+    /// and all of them are asked `attempts` times, 2 by default, before
+    /// the lookup fails. The options can also be given in the
+    /// `RES_OPTIONS` environment variable, e.g. `timeout:1 attempts:3`,
+    /// which overrides the file like it does for the C library.
+    Builtin,
+}
+
+impl Resolver {
+    /// The canonical name and addresses of `host`.
+    pub(crate) fn resolve(self, host: &str) -> Result<(String, IpAddrs), Error> {
+        let resolved = match self {
+            Resolver::System => system(host).map(|ips| (host.to_string(), ips)),
+            Resolver::Builtin => dns(host),
+        };
+        resolved.map_err(|e| ErrorKind::Dns.msg("Failed to resolve host").src(e))
+    }
+}
+
+fn system(host: &str) -> io::Result<IpAddrs> {
+    // the port is required but plays no part in the lookup
+    let addrs = (host, 0).to_socket_addrs()?;
+    Ok(addrs.map(|a| a.ip()).collect())
+}
+
+/// The parts of `/etc/resolv.conf` the builtin resolver uses, see resolv.conf(5).
+#[derive(Debug)]
+struct ResolvConf {
+    nameservers: Vec<SocketAddr>,
+    search: Vec<String>,
+    ndots: usize,
+    timeout: Duration,
+    attempts: u32,
+}
+
+static RESOLV_CONF: Lazy<ResolvConf> = Lazy::new(|| {
+    let mut text = fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    if let Ok(options) = std::env::var("RES_OPTIONS") {
+        text.push_str("\noptions ");
+        text.push_str(&options);
+    }
+    ResolvConf::parse(&text)
+});
+
+impl ResolvConf {
+    fn parse(text: &str) -> Self {
+        let mut conf = ResolvConf {
+            nameservers: vec![],
+            search: vec![],
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+        };
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    // the resolver only ever uses the first three
+                    let ip = words.next().and_then(|w| w.parse::<IpAddr>().ok());
+                    if let (Some(ip), true) = (ip, conf.nameservers.len() < 3) {
+                        conf.nameservers.push(SocketAddr::new(ip, 53));
+                    }
+                }
+                // the last of domain and search wins
+                Some("domain") => {
+                    conf.search = words.next().map(String::from).into_iter().collect()
+                }
+                Some("search") => conf.search = words.map(String::from).collect(),
+                Some("options") => {
+                    for option in words {
+                        let (name, value) = option.split_once(':').unwrap_or((option, ""));
+                        match (name, value.parse::<u32>()) {
+                            ("ndots", Ok(n)) => conf.ndots = n.min(15) as usize,
+                            ("timeout", Ok(n)) => {
+                                conf.timeout = Duration::from_secs(n.clamp(1, 30).into())
+                            }
+                            ("attempts", Ok(n)) => conf.attempts = n.clamp(1, 5),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if conf.nameservers.is_empty() {
+            conf.nameservers
+                .push(SocketAddr::from(([127, 0, 0, 1], 53)));
+        }
+        conf
+    }
+
+    // The names to look up for `name` in order. Names with at least ndots
+    // dots are tried as is before the search domains, others after.
+    fn candidates(&self, name: &str) -> Vec<String> {
+        if let Some(absolute) = name.strip_suffix('.') {
+            return vec![absolute.to_string()];
+        }
+        let searched = self.search.iter().map(|d| format!("{}.{}", name, d));
+        if name.matches('.').count() >= self.ndots {
+            std::iter::once(name.to_string()).chain(searched).collect()
+        } else {
+            searched.chain(std::iter::once(name.to_string())).collect()
+        }
+    }
+}
+
+// The addresses given for `name` in /etc/hosts, or else its A and AAAA
+// records, trying the search domains and nameservers of resolv.conf in turn.
+fn dns(name: &str) -> io::Result<(String, IpAddrs)> {
+    let hosts = fs::read_to_string("/etc/hosts").unwrap_or_default();
+    let ips = hosts_lookup(&hosts, name);
+    if !ips.is_empty() {
+        return Ok((name.to_string(), ips));
+    }
+
+    let conf = &*RESOLV_CONF;
+    // a failed lookup of one candidate doesn't stop the others from being
+    // tried, the error is returned if none has addresses
+    let mut last_err = None;
+    for candidate in conf.candidates(name) {
+        let v4 = query_servers(conf, &candidate, QueryType::A);
+        let v6 = query_servers(conf, &candidate, QueryType::AAAA);
+        let (v4, v6) = match (v4, v6) {
+            // one family failing is fine if the other is there
+            (Err(e), Err(_)) | (Err(e), Ok(None)) | (Ok(None), Err(e)) => {
+                last_err = Some(e);
+                continue;
+            }
+            (v4, v6) => (v4.ok().flatten(), v6.ok().flatten()),
+        };
+        match (v4, v6) {
+            (None, None) => continue,
+            (Some((qname, v4)), None) => return Ok((qname, v4)),
+            (None, Some((qname, v6))) => return Ok((qname, v6)),
+            (Some((qname, v4)), Some((_, v6))) => return Ok((qname, interleave(v6, v4))),
+        }
+    }
+    match last_err {
+        Some(e) => Err(e),
+        None => Ok((name.to_string(), vec![])),
+    }
+}
+
+// Lines are an address followed by the names it has, see hosts(5). Read
+// on every lookup like the C library does, so edits apply right away.
+fn hosts_lookup(hosts: &str, name: &str) -> IpAddrs {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    hosts
+        .lines()
+        .filter_map(|line| {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let ip = words.next()?.parse::<IpAddr>().ok()?;
+            words.any(|w| w.eq_ignore_ascii_case(name)).then_some(ip)
+        })
+        .collect()
+}
+
+// Alternate the families, IPv6 first, so that a broken IPv6 route costs
+// one failed attempt rather than all of them (RFC 8305 4).
+fn interleave(first: IpAddrs, second: IpAddrs) -> IpAddrs {
+    let mut out = Vec::with_capacity(first.len() + second.len());
+    let (mut a, mut b) = (first.into_iter(), second.into_iter());
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return out,
+            (x, y) => out.extend(x.into_iter().chain(y)),
+        }
+    }
+}
+
+// `None` when the name does not exist or has no records of the type. A
+// nameserver that doesn't answer in time, or fails, is skipped for the
+// next one.
+fn query_servers(
+    conf: &ResolvConf,
+    name: &str,
+    qtype: QueryType,
+) -> io::Result<Option<(String, IpAddrs)>> {
+    let mut last_err = None;
+    for _ in 0..conf.attempts {
+        for server in &conf.nameservers {
+            match query(*server, name, qtype, conf.timeout) {
+                Ok(found) => return Ok(found),
+                Err(e) => last_err = Some(e),
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::other("No nameserver to ask")))
+}
+
+fn query(
+    server: SocketAddr,
+    name: &str,
+    qtype: QueryType,
+    timeout: Duration,
+) -> io::Result<Option<(String, IpAddrs)>> {
+    let id = RandomState::new().build_hasher().finish() as u16;
+    let mut dmsg = Builder::new_query(id, true);
+    dmsg.add_question(name, false, qtype, QueryClass::IN);
+    // the query only fails to build if the name is too long
+    let plain = dmsg
+        .build()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "DNS query too long"))?;
+    let dmsg = with_edns(&plain);
+
+    let mut buf = exchange_udp(server, &dmsg, timeout)?;
+    // servers that don't know EDNS may refuse the OPT record, RFC 6891 7
+    let formerr = Header::parse(&buf)
+        .map(|h| h.response_code == ResponseCode::FormatError)
+        .unwrap_or(false);
+    let dmsg = if formerr {
+        buf = exchange_udp(server, &plain, timeout)?;
+        plain
+    } else {
+        dmsg
+    };
+    // a truncated answer may be missing records, ask again over TCP
+    let truncated = Header::parse(&buf).map(|h| h.truncated).unwrap_or(false);
+    if truncated {
+        buf = exchange_tcp(server, &dmsg, timeout)?;
+    }
+    let buf = &buf[..];
+    let packet = Packet::parse(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if packet.header.id != id {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "DNS answer to another query",
+        ));
+    }
+    match packet.header.response_code {
+        ResponseCode::NoError => {}
+        ResponseCode::NameError => return Ok(None),
+        _ => return Err(io::Error::other("DNS server failed")),
+    }
+    let q = packet.questions.first().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "DNS answer without the question",
+        )
+    })?;
+    let socks: IpAddrs = packet
+        .answers
+        .iter()
+        .filter_map(|ans| match ans.data {
+            A(ipv4) => {
+                let addr = ipv4.0;
+                Some(std::net::IpAddr::V4(addr))
+            }
+            AAAA(ipv6) => Some(std::net::IpAddr::V6(ipv6.0)),
+            _ => None,
+        })
+        .collect();
+    if socks.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some((q.qname.to_string(), socks)))
+}
+
+// The UDP payload size advertised with EDNS, small enough to avoid IP
+// fragmentation on any path, see https://dnsflagday.net/2020/.
+const EDNS_UDP_PAYLOAD: u16 = 1232;
+
+// The query with an OPT pseudo-record advertising EDNS_UDP_PAYLOAD, RFC
+// 6891 6.1.2, so that answers with many records fit in one datagram.
+fn with_edns(query: &[u8]) -> Vec<u8> {
+    let mut out = query.to_vec();
+    let arcount = u16::from_be_bytes([out[10], out[11]]) + 1;
+    out[10..12].copy_from_slice(&arcount.to_be_bytes());
+    out.push(0); // root name
+    out.extend_from_slice(&41u16.to_be_bytes()); // type OPT
+    out.extend_from_slice(&EDNS_UDP_PAYLOAD.to_be_bytes()); // class is the payload size
+    out.extend_from_slice(&[0; 4]); // extended rcode, version and flags
+    out.extend_from_slice(&[0; 2]); // no options
+    out
+}
+
+fn exchange_udp(server: SocketAddr, dmsg: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+    let base: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(base)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(server)?;
+
+    if socket.send(dmsg)? != dmsg.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            "Incomplete DNS query sent",
+        ));
+    }
+    let mut buf = vec![0; EDNS_UDP_PAYLOAD as usize];
+    let amt = socket.recv(&mut buf[..]).map_err(|e| match e.kind() {
+        // the query or the answer may have been lost on the way
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            io::Error::new(io::ErrorKind::TimedOut, "No DNS answer in time")
+        }
+        _ => e,
+    })?;
+    buf.truncate(amt);
+    Ok(buf)
+}
+
+// Over TCP, messages are preceded by their length as two bytes, RFC 1035 4.2.2.
+fn exchange_tcp(server: SocketAddr, dmsg: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut out = Vec::with_capacity(dmsg.len() + 2);
+    out.extend_from_slice(&(dmsg.len() as u16).to_be_bytes());
+    out.extend_from_slice(dmsg);
+    stream.write_all(&out)?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
@@ -1,12 +1,20 @@
+use std::borrow::Cow;
 use std::fmt;
-use std::io::{self, Read};
-
-use chunked_transfer::Decoder as ChunkDecoder;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::cache::CachedResponse;
 use crate::error::{Error, ErrorKind, ErrorKind::BadStatus};
 use crate::header::Headers;
+use crate::pool::{PoolReturn, PoolReturnRead};
 use crate::readers::*;
+use crate::retry::retry_after;
 use crate::stream::Stream;
+use crate::timings::Timings;
+use crate::url::Url;
+use crate::wire::{is_secret, WireLog};
 
 use std::convert::{TryFrom};
 
@@ -51,42 +59,168 @@ impl Status {
 }
 
 pub struct Response {
+    http_version: &'static str,
+    code: u16,
     status: Status,
     headers: Headers,
     reader: ComboReader,
+    timings: Timings,
+    // answers a HEAD request, so there is no body whatever the headers say
+    pub(crate) is_head: bool,
+    // the request url, after redirects
+    pub(crate) url: Option<Url>,
+    pub(crate) progress: Option<Arc<ProgressFn>>,
+    // bytes per second for reading the body
+    pub(crate) rate_limit: Option<u64>,
+    pub(crate) max_body_size: Option<u64>,
+    pub(crate) connection_reused: bool,
+    // hide credential headers in the Debug output
+    pub(crate) redact_debug: bool,
+    pub(crate) pool_return: Option<PoolReturn>,
 }
 
+/// Shows the status, the headers and how the body ends, e.g.
+///
+/// ```text
+/// Response[status: 200, status_text: 200 Ok, headers: [("Content-Type", "text/html"),
+/// ("Set-Cookie", "[redacted]")], body: chunked]
+/// ```
+///
+/// Credential and cookie headers are redacted unless turned off with
+/// [`AgentBuilder::redact_debug()`](crate::AgentBuilder::redact_debug).
 impl fmt::Debug for Response {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let status = self.status();
-        let text = status.to_str();
-        let status = status as u16;
-        write!(f, "Response[status: {}, status_text: {}", status, text,)?;
+        let text = self.status().to_str();
+        write!(f, "Response[status: {}, status_text: {}", self.code, text,)?;
+        let headers: Vec<_> = self
+            .headers()
+            .map(|(name, value)| match self.redact_debug && is_secret(name) {
+                true => (name, "[redacted]"),
+                false => (name, value),
+            })
+            .collect();
+        write!(f, ", headers: {:?}", headers)?;
+        let no_body = self.is_head || self.code == 204 || self.code == 304;
+        let chunked = self.header("transfer-encoding").is_some();
+        match (no_body, chunked, self.content_length()) {
+            (true, _, _) => write!(f, ", body: none")?,
+            (false, true, _) => write!(f, ", body: chunked")?,
+            (false, false, Some(len)) => write!(f, ", body: {} bytes", len)?,
+            (false, false, None) => write!(f, ", body: until close")?,
+        }
         write!(f, "]")
     }
 }
 
+// A response dropped unread reads the rest of a small body, so the
+// connection goes back to the pool, see [`Response::into_reader()`].
+impl Drop for Response {
+    fn drop(&mut self) {
+        if self.pool_return.is_some() {
+            drop(self.take_reader());
+        }
+    }
+}
+
 enum RR {
-    C(ChunkDecoder<ComboReader>),
-    L(std::io::Take<ComboReader>),
+    C(PoolReturnRead<ChunkDecoder<ComboReader>>),
+    L(PoolReturnRead<std::io::Take<ComboReader>>),
     R(ComboReader),
 }
 
 // Cannot RR directly because it would leak ComboReader to the consumer
-pub struct ResponseReader(RR);
+pub struct ResponseReader {
+    rr: RR,
+    timings: Timings,
+    bytes_read: u64,
+    // the length of the body, if known
+    total: Option<u64>,
+    progress: Option<Arc<ProgressFn>>,
+    throttle: Option<Throttle>,
+    max_size: Option<u64>,
+}
 
 impl Read for ResponseReader {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         use RR::*;
-        match &mut self.0 {
-            C(c) => c.read(buf),
-            L(c) => c.read(buf),
-            R(c) => c.read(buf),
+        if let Some(max) = self.max_size {
+            // no point reading a body known to be too large
+            if self.total.map(|total| total > max).unwrap_or(false) {
+                return Err(too_large());
+            }
         }
+        let len = match &self.throttle {
+            Some(throttle) => throttle.limit(buf.len()),
+            None => buf.len(),
+        };
+        let n = match &mut self.rr {
+            C(c) => c.read(&mut buf[..len]),
+            L(c) => c.read(&mut buf[..len]),
+            R(c) => c.read(&mut buf[..len]),
+        }?;
+        if let Some(throttle) = &mut self.throttle {
+            throttle.record(n);
+        }
+        self.bytes_read += n as u64;
+        if let Some(max) = self.max_size {
+            if self.bytes_read > max {
+                return Err(too_large());
+            }
+        }
+        if let (Some(progress), true) = (&self.progress, n > 0) {
+            progress(self.bytes_read, self.total);
+        }
+        if n == 0 && !buf.is_empty() && self.timings.body_done.is_none() {
+            self.timings.body_done = Some(Instant::now());
+        }
+        Ok(n)
     }
 }
 
+fn too_large() -> io::Error {
+    let msg = "response body is larger than the max body size";
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
 impl ResponseReader {
+    /// A trailer header, sent after a chunked body.
+    ///
+    /// Only available once the body is read to the end, before that and
+    /// for bodies that aren't chunked this is `None`.
+    ///
+    /// ```no_run
+    /// use std::io::Read;
+    ///
+    /// let url = ureq::Url::parse("http://example.com/grpc")?;
+    /// let mut reader = ureq::get(url).call()?.into_reader();
+    /// let mut body = Vec::new();
+    /// Read::read_to_end(&mut reader, &mut body)?;
+    /// let status = reader.trailer("grpc-status");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn trailer(&self, name: &str) -> Option<&str> {
+        let trailers = match &self.rr {
+            RR::C(c) => c.trailers()?,
+            _ => return None,
+        };
+        trailers
+            .header(name)
+            .and_then(|s| std::str::from_utf8(s).ok())
+            .map(|s| s.trim())
+    }
+
+    /// How many bytes of the body were read so far, after undoing the
+    /// chunked transfer encoding. Once read to the end, this is the
+    /// length of the body, also when there was no `Content-Length`.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Timings of the request, including when the body was read to the end.
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
     pub fn read_to_end(mut self, data: &mut [u8]) -> io::Result<&mut [u8]> {
         ReadToEndIterator::<Self>::new(&mut self, data)
             .try_fold(0, |acc, r| r.map(|c| acc + c))
@@ -99,6 +233,52 @@ impl Response {
         self.status
     }
 
+    /// When each step of the request happened, see [`Timings`].
+    ///
+    /// The body isn't read yet, its end is in
+    /// [`ResponseReader::timings()`].
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    /// Whether the request was sent on a connection from the agent's pool,
+    /// rather than a new one. Responses from a cache or a mock are on
+    /// neither.
+    ///
+    /// A reused connection skips connecting, so the [`timings()`](Response::timings)
+    /// have no connect or TLS steps.
+    pub fn connection_reused(&self) -> bool {
+        self.connection_reused
+    }
+
+    /// The url that gave this response, which differs from the one
+    /// requested when redirects were followed. A user name and password
+    /// in the url are left out.
+    ///
+    /// `None` for responses not from a request, like those of
+    /// `Response::from_stream()`.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/latest")?;
+    /// let resp = ureq::get(url).call()?;
+    /// println!("downloaded from {}", resp.get_url().unwrap());
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn get_url(&self) -> Option<&Url> {
+        self.url.as_ref()
+    }
+
+    /// The HTTP version of the response, `HTTP/1.1`, `HTTP/1.0` or, with
+    /// the `http2` feature, `HTTP/2.0`.
+    pub fn http_version(&self) -> &str {
+        self.http_version
+    }
+
+    /// The numeric status code, also for codes [`Status`] has no variant for.
+    pub fn status_code(&self) -> u16 {
+        self.code
+    }
+
     pub fn header(&self, name: &str) -> Option<&str> {
         self.headers
             .header(name)
@@ -106,6 +286,94 @@ impl Response {
             .map(|s| s.trim())
     }
 
+    /// The values of all headers named `name`, for those that may repeat
+    /// like `Set-Cookie` or `Via`.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/")?;
+    /// let resp = ureq::get(url).call()?;
+    /// for cookie in resp.header_all("set-cookie") {
+    ///     println!("{}", cookie);
+    /// }
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn header_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers
+            .header_all(name)
+            .filter_map(|s| std::str::from_utf8(s).ok())
+            .map(|s| s.trim())
+    }
+
+    /// The names of the headers, lowercased and each once, in the order
+    /// first received.
+    pub fn headers_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for (name, _) in self.headers() {
+            let name = name.to_ascii_lowercase();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// All headers as name and value, in the order received and including
+    /// repeats. Those that aren't UTF-8 are left out.
+    ///
+    /// ```no_run
+    /// let url = ureq::Url::parse("http://example.com/")?;
+    /// let resp = ureq::get(url).call()?;
+    /// for (name, value) in resp.headers() {
+    ///     println!("{}: {}", name, value);
+    /// }
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().filter_map(|(name, value)| {
+            let name = std::str::from_utf8(name).ok()?;
+            let value = std::str::from_utf8(value).ok()?;
+            Some((name, value.trim()))
+        })
+    }
+
+    /// The length of the body from the `Content-Length` header, if there is
+    /// a valid one.
+    ///
+    /// The body may be shorter if the connection breaks. It is ignored for
+    /// chunked bodies, and responses without a body, like those to `HEAD`
+    /// requests, may still give the length the body would have had.
+    pub fn content_length(&self) -> Option<u64> {
+        self.header("content-length")?.parse().ok()
+    }
+
+    /// How long the server asked to wait before trying again, with the
+    /// `Retry-After` header of e.g. a `429 Too Many Requests` or a
+    /// `503 Service Unavailable` response.
+    ///
+    /// The header is either a number of seconds or a date, a date in the
+    /// past is a zero wait.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.header("retry-after").and_then(retry_after)
+    }
+
+    /// The media type of the `Content-Type` header without its parameters,
+    /// e.g. `text/html` for `text/html; charset=utf-8`.
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("content-type")
+            .map(|h| h.split(';').next().unwrap_or(h).trim())
+    }
+
+    /// The `charset` parameter of the `Content-Type` header, unquoted,
+    /// e.g. `ISO-8859-1` for `text/html; charset="ISO-8859-1"`.
+    pub fn charset(&self) -> Option<&str> {
+        charset_from_content_type(self.header("content-type"))
+    }
+
+    #[cfg(feature = "http-interop")]
+    pub(crate) fn raw_headers(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.headers.iter()
+    }
+
     /// Turn this response into a `impl Read` of the body.
     ///
     /// 1. If `Transfer-Encoding: chunked`, the returned reader will unchunk it
@@ -114,111 +382,539 @@ impl Response {
     ///    length regardless of how many bytes the server sends.
     /// 3. If no length header, the reader is until server stream end.
     ///
-    pub fn into_reader(self) -> ResponseReader {
-        let is_close = self
-            .header("connection")
-            .map(|c| c.eq_ignore_ascii_case("close"))
-            .unwrap_or(false);
+    /// Responses to `HEAD` requests, and `204 No Content` and
+    /// `304 Not Modified` ones, have no body whatever their headers say.
+    ///
+    /// Unless the server asked to close it, a connection whose body has a
+    /// known end goes back to the agent's pool once the body is read. An
+    /// HTTP/1.0 response without `Connection: keep-alive` closes it.
+    /// Dropping the reader, or the response, before the end of the body
+    /// reads what's left if it's small, to keep the connection as well.
+    pub fn into_reader(mut self) -> ResponseReader {
+        self.take_reader()
+    }
+
+    // The body reader, leaving an empty one behind.
+    fn take_reader(&mut self) -> ResponseReader {
+        let has_token = |token: &str| {
+            self.header("connection")
+                .map(|c| c.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+                .unwrap_or(false)
+        };
+        // HTTP/1.0 closes the connection after the response unless it's
+        // asked to keep it alive.
+        let is_close = if self.http_version == "HTTP/1.0" {
+            !has_token("keep-alive")
+        } else {
+            has_token("close")
+        };
+
+        let no_body = self.is_head || self.code == 204 || self.code == 304;
 
-        let use_chunked = self
-            .header("transfer-encoding")
-            .map(|enc| !enc.is_empty()) // whatever it says, do chunked
-            .unwrap_or(false);
+        let use_chunked = !no_body
+            && self
+                .header("transfer-encoding")
+                .map(|enc| !enc.is_empty()) // whatever it says, do chunked
+                .unwrap_or(false);
 
-        let limit_bytes = if is_close {
+        let limit_bytes = if no_body {
+            Some(0)
+        } else if is_close {
             None
         } else {
-            self.header("content-length")
-                .and_then(|l| l.parse::<usize>().ok())
+            self.content_length()
         };
 
+        let total = match (no_body, use_chunked) {
+            (true, _) => Some(0),
+            (false, true) => None,
+            (false, false) => self.content_length(),
+        };
+        let pool_return = self.pool_return.take().filter(|_| !is_close);
+        let reader = mem::replace(&mut self.reader, memory_reader(vec![]));
+
         use RR::*;
         let rr = match (use_chunked, limit_bytes) {
-            (true, _) => C(ChunkDecoder::new(self.reader)),
-            (false, Some(len)) => L(self.reader.take(len as u64)),
-            (false, None) => R(self.reader),
+            (true, _) => C(PoolReturnRead::new(pool_return, ChunkDecoder::new(reader))),
+            (false, Some(len)) => L(PoolReturnRead::new(pool_return, reader.take(len))),
+            (false, None) => R(reader),
         };
 
-        ResponseReader(rr)
+        ResponseReader {
+            rr,
+            timings: self.timings,
+            bytes_read: 0,
+            total,
+            progress: self.progress.take(),
+            throttle: self.rate_limit.map(Throttle::new),
+            max_size: self.max_body_size,
+        }
     }
 
-    pub(crate) fn do_from_stream(mut stream: Stream) -> Result<Response, Error> {
+    /// Read the body into memory, so the connection goes back to the pool
+    /// right away. A chunked body is given a `Content-Length` in place of
+    /// its `Transfer-Encoding`, and trailers are dropped. A body over the
+    /// max body size is an error, like when reading it.
+    pub(crate) fn into_buffered(self) -> Result<Response, Error> {
+        let chunked = self.header("transfer-encoding").is_some();
+        let mut head = vec![];
+        for (name, value) in self.headers.iter() {
+            if chunked
+                && (name.eq_ignore_ascii_case(b"transfer-encoding")
+                    || name.eq_ignore_ascii_case(b"content-length"))
+            {
+                continue;
+            }
+            head.extend_from_slice(name);
+            head.push(b':');
+            head.extend_from_slice(value);
+            head.extend_from_slice(b"\r\n");
+        }
+
+        let (http_version, code, is_head) = (self.http_version, self.code, self.is_head);
+        let url = self.url.clone();
+        let (reused, redact_debug) = (self.connection_reused, self.redact_debug);
+        let max_body_size = self.max_body_size;
+        let mut reader = self.into_reader();
+        let mut body = vec![];
+        Read::read_to_end(&mut reader, &mut body)?;
+        let timings = *reader.timings();
+        if chunked {
+            head.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
+        }
+
+        let headers = Headers::try_from(&head[..])?;
+        let mut resp = Response::from_memory(http_version, code, headers, body, timings);
+        resp.is_head = is_head;
+        resp.url = url;
+        resp.connection_reused = reused;
+        resp.redact_debug = redact_debug;
+        resp.max_body_size = max_body_size;
+        Ok(resp)
+    }
+
+    /// A response from a [`Cache`](crate::cache::Cache).
+    pub(crate) fn from_cached(
+        cached: &CachedResponse,
+        timings: Timings,
+    ) -> Result<Response, Error> {
+        let mut head = vec![];
+        for (name, value) in &cached.headers {
+            head.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        let headers = Headers::try_from(&head[..])?;
+        let body = cached.body.clone();
+        let resp = Response::from_memory("HTTP/1.1", cached.status, headers, body, timings);
+        Ok(resp)
+    }
+
+    fn from_memory(
+        http_version: &'static str,
+        code: u16,
+        headers: Headers,
+        body: Vec<u8>,
+        timings: Timings,
+    ) -> Response {
+        Response {
+            http_version,
+            code,
+            status: Status::from(code),
+            headers,
+            reader: memory_reader(body),
+            timings,
+            is_head: false,
+            url: None,
+            progress: None,
+            rate_limit: None,
+            max_body_size: None,
+            connection_reused: false,
+            redact_debug: true,
+            pool_return: None,
+        }
+    }
+
+    /// Turn this response into a reader of the body, like
+    /// [`into_reader()`](Response::into_reader), that also writes every
+    /// byte read to `writer`.
+    ///
+    /// E.g. to save the raw body to a file while parsing it.
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::Read;
+    ///
+    /// let url = ureq::Url::parse("http://example.com/data.json")?;
+    /// let resp = ureq::get(url).call()?;
+    /// let mut reader = resp.tee(File::create("data.json")?);
+    /// let mut body = String::new();
+    /// reader.read_to_string(&mut body)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn tee<W: Write>(self, writer: W) -> TeeReader<ResponseReader, W> {
+        TeeReader::new(self.into_reader(), writer)
+    }
+
+    /// Read the body into a `String`, refusing bodies over 10 megabytes.
+    ///
+    /// See [`into_string_with_limit()`](Response::into_string_with_limit).
+    pub fn into_string(self) -> io::Result<String> {
+        self.into_string_with_limit(INTO_STRING_LIMIT)
+    }
+
+    /// Read the body into a `String`, refusing bodies over `limit` bytes.
+    ///
+    /// With the `charset` feature, the body is decoded from the charset in
+    /// the `Content-Type` header, e.g. `text/html; charset=ISO-8859-1`. A
+    /// missing or unknown charset decodes as UTF-8, and bytes invalid in
+    /// the charset become U+FFFD rather than an error. Without the feature
+    /// the body must be valid UTF-8.
+    ///
+    /// A body over the limit is an [`io::ErrorKind::InvalidData`] error, so
+    /// a misbehaving server can't make this read unbounded amounts of data
+    /// into memory.
+    pub fn into_string_with_limit(self, limit: usize) -> io::Result<String> {
+        #[cfg(feature = "charset")]
+        let encoding = encoding_rs::Encoding::for_label(
+            self.charset().unwrap_or(DEFAULT_CHARACTER_SET).as_bytes(),
+        )
+        .unwrap_or(encoding_rs::UTF_8);
+
+        let mut buf = Vec::new();
+        self.into_reader()
+            .take(limit as u64 + 1)
+            .read_to_end(&mut buf)?;
+        if buf.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "response too big for into_string",
+            ));
+        }
+
+        #[cfg(feature = "charset")]
+        {
+            let (text, _, _) = encoding.decode(&buf);
+            Ok(text.into_owned())
+        }
+        #[cfg(not(feature = "charset"))]
+        {
+            String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+    }
+
+    /// Read the body as JSON and deserialize it into `T`.
+    ///
+    /// Requires the `json` feature. Malformed JSON, or JSON that doesn't
+    /// fit `T`, is an [`ErrorKind::Json`] error, while failing to read the
+    /// body is an [`ErrorKind::Io`] one.
+    ///
+    /// ```no_run
+    /// #[derive(serde::Deserialize)]
+    /// struct Hello {
+    ///     hello: String,
+    /// }
+    ///
+    /// let url = ureq::Url::parse("http://example.com/hello.json")?;
+    /// let hello: Hello = ureq::get(url).call()?.into_json()?;
+    /// # Ok::<(), ureq::Error>(())
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn into_json<T: serde::de::DeserializeOwned>(self) -> Result<T, Error> {
+        serde_json::from_reader(self.into_reader()).map_err(|e| {
+            if e.is_io() {
+                Error::from(io::Error::from(e))
+            } else {
+                ErrorKind::Json.msg("Failed to parse JSON").src(e)
+            }
+        })
+    }
+
+    /// Parse a response from the status line and headers at the start of `stream`.
+    ///
+    /// ```
+    /// use ureq::{Response, Stream};
+    ///
+    /// let stream = Stream::from_vec(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi".to_vec());
+    /// let resp = Response::from_stream(stream).unwrap();
+    /// assert_eq!(resp.header("content-length"), Some("2"));
+    /// ```
+    #[cfg(feature = "testing")]
+    pub fn from_stream(stream: Stream) -> Result<Response, Error> {
+        Response::do_from_stream(stream, Timings::new(), MAX_HEAD_SIZE, false, None)
+    }
+
+    /// `max_head` limits the size of the status line and headers, `lenient`
+    /// accepts bare LF line ends, folded header lines and sloppy status
+    /// lines. The arrival of
+    /// the first byte is added to `timings`, and each head read is given
+    /// to `wire_log`.
+    pub(crate) fn do_from_stream(
+        mut stream: Stream,
+        mut timings: Timings,
+        max_head: usize,
+        lenient: bool,
+        wire_log: Option<&WireLog>,
+    ) -> Result<Response, Error> {
         //
         // HTTP/1.1 200 OK\r\n
-        //let (mut headers, carryover) = read_status_and_headers(&mut stream)?;
-        let b = read_status_and_headers(&mut stream)?;
+        let first_byte = &mut timings.first_byte;
+        let mut b = read_status_and_headers(&mut stream, vec![], 0, max_head, first_byte)?;
+        let (http_version, code, headers) = loop {
+            if let Some(wire_log) = wire_log {
+                wire_log.response(&b.buf[..b.head_len + 2]);
+            }
+            let headers = match lenient {
+                true => Cow::Owned(unfold_head(&b.buf[..b.head_len])),
+                false => Cow::Borrowed(&b.buf[..b.head_len]),
+            };
 
-        let headers = &b.buf[..b.head_len];
+            let i = &headers
+                .iter()
+                .position(|x| *x == b'\n')
+                .ok_or_else(|| ErrorKind::BadStatus.msg("Missing Status Line"))?;
+            let status_line = &headers[..i + 1];
+            let (http_version, code) = match lenient {
+                true => parse_status_line_lenient(status_line)?,
+                false => parse_status_line_from_header(status_line)?,
+            };
 
-        let i = &headers.iter().position(|x| *x == b'\n')
-            .ok_or_else(|| ErrorKind::BadStatus.msg("Missing Status Line"))?;
-        let status_line = &headers[..i + 1];
-        let (_, status) = parse_status_line_from_header(status_line)?;
+            // 100 Continue, 103 Early Hints and the like precede the final
+            // response. 101 Switching Protocols is final, what follows isn't
+            // HTTP anymore.
+            if (100..200).contains(&code) && code != 101 {
+                let start = b.head_len + 2;
+                let end = start + b.carry_len;
+                b.buf.copy_within(start..end, 0);
+                b = read_status_and_headers(&mut stream, b.buf, b.carry_len, max_head, first_byte)?;
+                continue;
+            }
 
-        let headers = Headers::try_from(&headers[i+1..b.head_len])?;
-        //let carryover = b.buf[b.head_len..b.head_len+b.carry_len].try_into().unwrap();
+            break (http_version, code, Headers::try_from(&headers[i + 1..])?);
+        };
 
         let reader = ComboReader {
+            pos: b.head_len + 2,
             co: b,
             st: stream,
         };
 
         Ok(Response {
-            status,
+            http_version,
+            code,
+            status: Status::from(code),
             headers,
             reader,
+            timings,
+            is_head: false,
+            url: None,
+            progress: None,
+            rate_limit: None,
+            max_body_size: None,
+            connection_reused: false,
+            redact_debug: true,
+            pool_return: None,
         })
     }
+
+    /// A response whose head was read by other means than parsing
+    /// HTTP/1.1, with the body read from `stream`.
+    #[cfg(feature = "http2")]
+    pub(crate) fn from_parts(
+        code: u16,
+        headers: Headers,
+        stream: Stream,
+        timings: Timings,
+    ) -> Response {
+        let reader = ComboReader {
+            co: Buffer {
+                buf: vec![],
+                head_len: 0,
+                carry_len: 0,
+            },
+            pos: 2,
+            st: stream,
+        };
+        Response {
+            http_version: "HTTP/2.0",
+            code,
+            status: Status::from(code),
+            headers,
+            reader,
+            timings,
+            is_head: false,
+            url: None,
+            progress: None,
+            rate_limit: None,
+            max_body_size: None,
+            connection_reused: false,
+            redact_debug: true,
+            pool_return: None,
+        }
+    }
+}
+
+const INTO_STRING_LIMIT: usize = 10 * 1_024 * 1_024;
+
+/// Default for [`AgentBuilder::max_response_header_size()`](crate::AgentBuilder::max_response_header_size).
+pub(crate) const MAX_HEAD_SIZE: usize = 64 * 1_024;
+// enough for the heads of most responses in one read
+const INITIAL_HEAD_BUFFER: usize = 8 * 1_024;
+
+#[cfg(feature = "charset")]
+const DEFAULT_CHARACTER_SET: &str = "utf-8";
+
+// A reader of a body already in memory.
+fn memory_reader(body: Vec<u8>) -> ComboReader {
+    ComboReader {
+        co: Buffer {
+            buf: vec![],
+            head_len: 0,
+            carry_len: 0,
+        },
+        pos: 2,
+        st: Stream::Memory(io::Cursor::new(body), vec![]),
+    }
 }
 
-// HTTP/1.1 200 OK\r\n
-fn parse_status_line_from_header(s: &[u8]) -> Result<(&'static str, Status), Error> {
+// text/html; charset="ISO-8859-1" => ISO-8859-1
+fn charset_from_content_type(header: Option<&str>) -> Option<&str> {
+    header
+        .into_iter()
+        .flat_map(|h| h.split(';').skip(1))
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            if key.trim().eq_ignore_ascii_case("charset") {
+                Some(value.trim().trim_matches('"'))
+            } else {
+                None
+            }
+        })
+        .next()
+}
+
+// HTTP/1.1 200 OK\r\n or HTTP/1.0 200 OK\r\n
+fn parse_status_line_from_header(s: &[u8]) -> Result<(&'static str, u16), Error> {
     if s.len() < 12 {
         Err(BadStatus.msg("Status line isn't formatted correctly"))
-    } else if b"HTTP/1.1 " != &s[..9] {
+    } else if b"HTTP/1.1 " != &s[..9] && b"HTTP/1.0 " != &s[..9] {
         Err(BadStatus.msg("HTTP version not formatted correctly"))
+    } else if !s.ends_with(b"\r\n") {
+        Err(BadStatus.msg("Status line must end with CRLF"))
     } else if s[9..12].iter().any(|c| !c.is_ascii_digit()) || s[12] != b' ' {
         Err(BadStatus.msg("HTTP status code must be a 3 digit number"))
     } else {
         let status =
             ((s[9] - b'0') as u16 * 100) + (s[10] - b'0') as u16 * 10 + (s[11] - b'0') as u16;
-        let status = Status::from(status);
         std::str::from_utf8(&s[12..])
             .map_err(|_| BadStatus.new())
-            .map(|_| ("HTTP/1.1", status))
+            .map(|_| {
+                let version = if s[7] == b'0' { "HTTP/1.0" } else { "HTTP/1.1" };
+                (version, status)
+            })
+    }
+}
+
+// Status lines of servers that get the spacing wrong, `HTTP/1.1 200OK`,
+// `HTTP/1.1  200 OK` or `HTTP/1.1 200` without the space before the missing
+// reason. The version and a 3 digit status are still required.
+fn parse_status_line_lenient(s: &[u8]) -> Result<(&'static str, u16), Error> {
+    let s = s.strip_suffix(b"\r\n").unwrap_or(s);
+    let version = match s.get(..8).map(|v| v.to_ascii_uppercase()).as_deref() {
+        Some(b"HTTP/1.0") => "HTTP/1.0",
+        Some(b"HTTP/1.1") => "HTTP/1.1",
+        _ => return Err(BadStatus.msg("HTTP version not formatted correctly")),
+    };
+    let rest = s[8..].trim_ascii_start();
+    match rest.get(..3) {
+        Some(code) if code.iter().all(|c| c.is_ascii_digit()) => {
+            let status = code.iter().fold(0, |n, c| n * 10 + (c - b'0') as u16);
+            Ok((version, status))
+        }
+        _ => Err(BadStatus.msg("HTTP status code must be a 3 digit number")),
     }
 }
 
-pub(crate) struct Buffer<const N: usize> {
-    pub(crate) buf: [u8; N],
+// After the first empty line, \n\n or \n\r\n, searching from `from`.
+fn find_body_start(buf: &[u8], from: usize) -> Option<usize> {
+    (from..buf.len())
+        .filter(|&j| buf[j] == b'\n')
+        .find_map(|j| match &buf[j + 1..] {
+            [b'\n', ..] => Some(j + 2),
+            [b'\r', b'\n', ..] => Some(j + 3),
+            _ => None,
+        })
+}
+
+// Bare LF line ends and obsolete line folding, RFC 7230 3.2.4, turned into
+// plain CRLF lines. A folded line is joined to the one before with a space.
+fn unfold_head(head: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(head.len() + 2);
+    for line in head.split(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+        if matches!(line[0], b' ' | b'\t') && !out.is_empty() {
+            out.truncate(out.len() - 2);
+            out.push(b' ');
+            out.extend_from_slice(line.trim_ascii_start());
+        } else {
+            out.extend_from_slice(line);
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+pub(crate) struct Buffer {
+    pub(crate) buf: Vec<u8>,
     pub(crate) head_len: usize,
     pub(crate) carry_len: usize,
 }
 
-fn read_status_and_headers(reader: &mut Stream) -> io::Result<Buffer<16_384>> {
-    let mut buffer = [0; 8192 * 2];
-    let mut ri = ReadIterator::<Stream>::new(reader, &mut buffer);
-
-    if let Some(res) = ri.next() {
-        let c = res?;
-        match &buffer[..c].windows(4).position(|win| win == b"\r\n\r\n") {
-            Some(i) => {
-                let b = Buffer {
-                    buf: buffer,
-                    head_len: i+2,
-                    carry_len: c-(i+4),
-                };
-                return Ok(b);
+// Reads until the end of a head, the first `filled` bytes of `buffer` are
+// already read. The buffer grows as needed, up to `max_size`. `first_byte`
+// is set by the first read that returns data.
+//
+// The head ends at the first empty line, also with bare LF line ends so
+// that those are an error rather than a wait for a CRLF that never comes.
+fn read_status_and_headers(
+    reader: &mut Stream,
+    mut buffer: Vec<u8>,
+    mut filled: usize,
+    max_size: usize,
+    first_byte: &mut Option<Instant>,
+) -> Result<Buffer, Error> {
+    let mut searched = 0;
+    loop {
+        if let Some(body_start) = find_body_start(&buffer[..filled], searched) {
+            // as if the head ended with CRLF CRLF
+            return Ok(Buffer {
+                buf: buffer,
+                head_len: body_start - 2,
+                carry_len: filled - body_start,
+            });
+        }
+        // the terminator may straddle what's read and what comes next
+        searched = filled.saturating_sub(3);
+        if filled == buffer.len() {
+            if buffer.len() >= max_size {
+                return Err(ErrorKind::BadHeader.msg("Response head larger than the maximum size"));
             }
-            None => {
+            let len = (buffer.len() * 2).max(INITIAL_HEAD_BUFFER).min(max_size);
+            buffer.resize(len, 0);
+        }
+        match reader.read(&mut buffer[filled..])? {
+            0 => {
                 return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                    io::ErrorKind::UnexpectedEof,
                     "Failed to fetch HTTP headers in given buffer",
-                ));
+                )
+                .into())
+            }
+            n => {
+                first_byte.get_or_insert_with(Instant::now);
+                filled += n;
             }
         }
     }
-    Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-        "Failed to fetch HTTP headers in given buffer",
-    ))
 }
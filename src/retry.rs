@@ -0,0 +1,135 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+use crate::date::parse_http_date;
+use crate::error::Error;
+use crate::response::Response;
+
+/// Retries of failed requests, off unless set on a request.
+///
+/// Only idempotent methods (`GET`, `HEAD`, `PUT`, `DELETE`, `OPTIONS` and
+/// `TRACE`) are retried, and only when the body can be sent again, which
+/// rules out bodies from [`Request::send()`](crate::Request::send).
+///
+/// DNS failures, refused connections and connections reset or closed by
+/// the server are retried. Responses are retried when their status is one
+/// of [`statuses()`](RetryPolicy::statuses), in which case a `Retry-After`
+/// header is honored. After the last attempt its error or response is
+/// returned as is.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use ureq::RetryPolicy;
+///
+/// let policy = RetryPolicy::new()
+///     .max_attempts(4)
+///     .backoff(Duration::from_millis(200), Duration::from_secs(5))
+///     .statuses(&[429, 503]);
+/// let url = ureq::Url::parse("http://example.com/")?;
+/// let resp = ureq::get(url).retry(policy).call()?;
+/// # Ok::<(), ureq::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    statuses: Vec<u16>,
+}
+
+impl RetryPolicy {
+    /// Three attempts, waiting 100ms and then 200ms with jitter, and no
+    /// retries on status codes.
+    pub fn new() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
+            statuses: vec![],
+        }
+    }
+
+    /// Total number of attempts, including the first one.
+    pub fn max_attempts(mut self, n: u32) -> Self {
+        self.max_attempts = n.max(1);
+        self
+    }
+
+    /// The wait before the first retry, doubled for each one after that
+    /// but never more than `max`.
+    ///
+    /// `max` also caps the wait asked for by `Retry-After`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Wait a random 50% to 100% of the backoff, so that clients failing
+    /// together don't retry together. On by default.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Retry responses with these status codes, e.g. `429` and `503`.
+    pub fn statuses(mut self, codes: &[u16]) -> Self {
+        self.statuses = codes.to_vec();
+        self
+    }
+
+    /// How long to wait before attempt `attempt + 1`, or `None` to give up.
+    pub(crate) fn wait(&self, attempt: u32, result: &Result<Response, Error>) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let backoff = self.backoff_for(attempt);
+        match result {
+            Ok(resp) if self.statuses.contains(&resp.status_code()) => {
+                let wait = resp.retry_after().unwrap_or(backoff);
+                Some(wait.min(self.max_backoff))
+            }
+            Ok(_) => None,
+            Err(e) if e.is_retryable() => Some(backoff),
+            Err(_) => None,
+        }
+    }
+
+    fn backoff_for(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        let backoff = self
+            .initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        if self.jitter {
+            // a random fraction in [0.5, 1), seeded from the OS like the
+            // multipart boundary
+            let r = RandomState::new().build_hasher().finish();
+            backoff.mul_f64(0.5 + (r >> 11) as f64 / (1u64 << 54) as f64)
+        } else {
+            backoff
+        }
+    }
+}
+
+/// Methods that may be sent twice without changing the outcome, RFC 7231 4.2.2.
+pub(crate) fn is_idempotent(method: &str) -> bool {
+    matches!(
+        method,
+        "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" | "TRACE"
+    )
+}
+
+// Retry-After is either delay-seconds or an HTTP date, RFC 7231 7.1.3.
+pub(crate) fn retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = parse_http_date(value)?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
@@ -0,0 +1,47 @@
+use crate::unit::Header;
+use crate::url::Url;
+
+pub(crate) type SignFn = dyn Fn(&SignRequest) -> Vec<(String, String)> + Send + Sync;
+
+/// A request as it's about to be sent, for
+/// [`AgentBuilder::signer()`](crate::AgentBuilder::signer) to sign.
+///
+/// The headers are final, including those ureq adds like `Host`,
+/// `Content-Length` and cookies.
+pub struct SignRequest<'a> {
+    pub(crate) method: &'a str,
+    pub(crate) url: &'a Url,
+    pub(crate) headers: &'a [Header<'a>],
+    pub(crate) body: Option<&'a [u8]>,
+}
+
+impl<'a> SignRequest<'a> {
+    /// The request method, e.g. `GET`.
+    pub fn method(&self) -> &str {
+        self.method
+    }
+
+    pub fn url(&self) -> &Url {
+        self.url
+    }
+
+    /// All headers as name and value, in the order they are sent.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(n, v)| (n.as_ref(), v.as_ref()))
+    }
+
+    /// The value of a header, names are case insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+
+    /// The body, to compute a digest or signature of. Empty for requests
+    /// without one, and `None` for bodies streamed from a reader with
+    /// [`Request::send()`](crate::Request::send), which can't be read
+    /// ahead of sending.
+    pub fn body(&self) -> Option<&[u8]> {
+        self.body
+    }
+}
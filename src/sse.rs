@@ -0,0 +1,257 @@
+//! Reading Server-Sent Events, the `text/event-stream` of the HTML
+//! EventSource API.
+//!
+//! [`EventSource`] connects to a url and yields the events the server
+//! sends, reconnecting when the connection drops and asking to continue
+//! after the last event received with `Last-Event-ID`.
+//!
+//! ```no_run
+//! use ureq::sse::EventSource;
+//!
+//! let agent = ureq::agent();
+//! let url = ureq::Url::parse("http://example.com/updates")?;
+//! for event in EventSource::new(&agent, url) {
+//!     let event = event?;
+//!     println!("{}: {}", event.event, event.data);
+//! }
+//! # Ok::<(), ureq::Error>(())
+//! ```
+//!
+//! To parse a response fetched by other means, wrap its reader in
+//! [`Events`].
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::thread;
+use std::time::Duration;
+
+use crate::agent::Agent;
+use crate::error::{Error, ErrorKind};
+use crate::response::ResponseReader;
+use crate::url::Url;
+
+// Until the server says otherwise with a `retry:` field.
+const DEFAULT_RETRY: Duration = Duration::from_secs(3);
+
+/// An event of an event stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// The last `id:` the server sent, on this event or an earlier one.
+    pub id: Option<String>,
+    /// The `event:` type, `message` unless given.
+    pub event: String,
+    /// The `data:` lines, joined with newlines.
+    pub data: String,
+}
+
+/// Parses the events of a `text/event-stream` body.
+///
+/// Comments, lines starting with `:`, which servers send to keep the
+/// connection alive, are skipped.
+///
+/// ```
+/// use ureq::sse::Events;
+///
+/// let body = "id: 1\ndata: hello\ndata: world\n\n: keep-alive\n\n";
+/// let mut events = Events::new(body.as_bytes());
+/// let event = events.next().unwrap()?;
+/// assert_eq!(event.id.as_deref(), Some("1"));
+/// assert_eq!(event.data, "hello\nworld");
+/// assert!(events.next().is_none());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Events<R> {
+    reader: BufReader<R>,
+    last_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl<R: Read> Events<R> {
+    pub fn new(reader: R) -> Self {
+        Events {
+            reader: BufReader::new(reader),
+            last_id: None,
+            retry: None,
+        }
+    }
+
+    /// The reconnection delay the server asked for with `retry:`.
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+}
+
+impl<R: Read> Iterator for Events<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut event = String::new();
+        let mut data = String::new();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                // an event cut short by the end of the stream is dropped
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            let line = line.trim_end_matches('\n').trim_end_matches('\r');
+
+            if line.is_empty() {
+                if data.is_empty() {
+                    event.clear();
+                    continue;
+                }
+                data.pop();
+                return Some(Ok(Event {
+                    id: self.last_id.clone(),
+                    event: match event.is_empty() {
+                        true => "message".to_string(),
+                        false => event,
+                    },
+                    data,
+                }));
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "data" => {
+                    data.push_str(value);
+                    data.push('\n');
+                }
+                "event" => event = value.to_string(),
+                "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+                "retry" => {
+                    if let Ok(ms) = value.parse() {
+                        self.retry = Some(Duration::from_millis(ms));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The events of a url, reconnecting when the connection drops.
+///
+/// Connection errors are yielded, and the next call reconnects after the
+/// delay the server asked for, 3 seconds by default. An error status, or
+/// a response that isn't `text/event-stream`, ends the iteration. So does
+/// a `204 No Content`, the server's way to say there are no more events.
+pub struct EventSource {
+    agent: Agent,
+    url: Url,
+    events: Option<Events<ResponseReader>>,
+    last_id: Option<String>,
+    retry: Duration,
+    reconnect: bool,
+    connected_before: bool,
+    done: bool,
+}
+
+impl EventSource {
+    pub fn new(agent: &Agent, url: Url) -> Self {
+        EventSource {
+            agent: agent.clone(),
+            url,
+            events: None,
+            last_id: None,
+            retry: DEFAULT_RETRY,
+            reconnect: true,
+            connected_before: false,
+            done: false,
+        }
+    }
+
+    /// Whether to reconnect when the connection drops or fails, rather
+    /// than end the iteration. Defaults to `true`.
+    pub fn reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// The id of the last event received, sent as `Last-Event-ID` when
+    /// reconnecting.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_id.as_deref()
+    }
+
+    fn connect(&mut self) -> Result<Option<Events<ResponseReader>>, Error> {
+        if self.connected_before {
+            thread::sleep(self.retry);
+        }
+        self.connected_before = true;
+        let mut req = self
+            .agent
+            .get(self.url.clone())
+            .set("Accept", "text/event-stream")
+            .set("Cache-Control", "no-cache");
+        if let Some(id) = &self.last_id {
+            req = req.set("Last-Event-ID", id);
+        }
+        let resp = req.call()?;
+        if resp.status_code() == 204 {
+            return Ok(None);
+        }
+        if resp.content_type() != Some("text/event-stream") {
+            return Err(ErrorKind::BadHeader.msg("Response is not an event stream"));
+        }
+        let mut events = Events::new(resp.into_reader());
+        events.last_id = self.last_id.clone();
+        Ok(Some(events))
+    }
+}
+
+impl Iterator for EventSource {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.done {
+                return None;
+            }
+            let events = match &mut self.events {
+                Some(events) => events,
+                None => match self.connect() {
+                    Ok(Some(events)) => self.events.insert(events),
+                    Ok(None) => {
+                        self.done = true;
+                        return None;
+                    }
+                    Err(e) => {
+                        // an error status or not an event stream won't
+                        // change by trying again
+                        let lasting =
+                            matches!(e, Error::Status(..)) || e.kind() == ErrorKind::BadHeader;
+                        self.done = lasting || !self.reconnect;
+                        return Some(Err(e));
+                    }
+                },
+            };
+
+            let next = events.next();
+            if let Some(retry) = events.retry() {
+                self.retry = retry;
+            }
+            match next {
+                Some(Ok(event)) => {
+                    self.last_id = event.id.clone();
+                    return Some(Ok(event));
+                }
+                Some(Err(e)) => {
+                    self.events = None;
+                    self.done = !self.reconnect;
+                    return Some(Err(e.into()));
+                }
+                None => {
+                    self.events = None;
+                    self.done = !self.reconnect;
+                }
+            }
+        }
+    }
+}
@@ -1,32 +1,91 @@
-use dns_parser::RData::A;
-use dns_parser::{Builder, Packet, QueryClass, QueryType};
 use std::io::{self, Read, Write};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpStream, UdpSocket};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
 
-#[cfg(feature = "tls")]
-use crate::agent::Agent;
-use crate::error::Error;
+use socket2::{SockRef, TcpKeepalive};
 
 #[cfg(feature = "tls")]
-use crate::error::ErrorKind;
-
-type IpAddrs = Vec<IpAddr>;
+use crate::agent::Agent;
+use crate::error::{Error, ErrorKind};
+#[cfg(feature = "http2")]
+use crate::h2::H2Body;
+use crate::resolve::Resolver;
+use crate::timings::Timings;
+#[cfg(feature = "vcr")]
+use crate::vcr::Recorder;
 
 pub enum Stream {
     Http(TcpStream),
     #[cfg(feature = "tls")]
     Https(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+    /// Reads from an in-memory buffer, writes are collected and discarded.
+    Memory(io::Cursor<Vec<u8>>, Vec<u8>),
+    /// A real stream whose traffic is captured to a cassette.
+    #[cfg(feature = "vcr")]
+    Record(Box<Recorder>),
+    /// The body of a response on an HTTP/2 connection.
+    #[cfg(feature = "http2")]
+    H2(Box<H2Body>),
+}
+
+impl Stream {
+    /// A stream that reads `v` and accepts but ignores all writes.
+    ///
+    /// Lets code consuming a [`Response`](crate::Response) be tested
+    /// against canned bytes instead of a socket.
+    #[cfg(any(feature = "testing", feature = "vcr"))]
+    pub fn from_vec(v: Vec<u8>) -> Stream {
+        Stream::Memory(io::Cursor::new(v), vec![])
+    }
+
+    /// Whether the server picked HTTP/2 during the TLS handshake.
+    #[cfg(feature = "http2")]
+    pub(crate) fn is_h2(&self) -> bool {
+        match self {
+            Stream::Https(stream) => stream.conn.alpn_protocol() == Some(b"h2"),
+            _ => false,
+        }
+    }
+
+    /// The socket underneath, if there is one.
+    pub(crate) fn socket(&self) -> Option<&TcpStream> {
+        match self {
+            Stream::Http(sock) => Some(sock),
+            #[cfg(feature = "tls")]
+            Stream::Https(stream) => Some(stream.get_ref()),
+            Stream::Memory(..) => None,
+            #[cfg(feature = "vcr")]
+            Stream::Record(rec) => rec.inner.socket(),
+            #[cfg(feature = "http2")]
+            Stream::H2(body) => body.socket(),
+        }
+    }
+}
+
+// Socket timeouts are WouldBlock on unix, TimedOut elsewhere. Make them
+// TimedOut everywhere, WouldBlock would suggest a nonblocking socket.
+fn timed_out(e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::WouldBlock {
+        io::Error::new(io::ErrorKind::TimedOut, "timed out on socket")
+    } else {
+        e
+    }
 }
 
 impl Read for Stream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
-            Stream::Http(sock) => sock.read(buf),
+            Stream::Http(sock) => sock.read(buf).map_err(timed_out),
             #[cfg(feature = "tls")]
             Stream::Https(stream) => match stream.read(buf) {
                 Err(ref e) if is_close_notify(e) => Ok(0),
-                v => v,
+                v => v.map_err(timed_out),
             },
+            Stream::Memory(cursor, _) => cursor.read(buf),
+            #[cfg(feature = "vcr")]
+            Stream::Record(rec) => rec.read(buf),
+            #[cfg(feature = "http2")]
+            Stream::H2(body) => body.read(buf).map_err(timed_out),
         }
     }
 }
@@ -46,9 +105,17 @@ fn is_close_notify(e: &std::io::Error) -> bool {
 impl Write for Stream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         match self {
-            Stream::Http(sock) => sock.write(buf),
+            Stream::Http(sock) => sock.write(buf).map_err(timed_out),
             #[cfg(feature = "tls")]
-            Stream::Https(stream) => stream.write(buf),
+            Stream::Https(stream) => stream.write(buf).map_err(timed_out),
+            Stream::Memory(_, sink) => sink.write(buf),
+            #[cfg(feature = "vcr")]
+            Stream::Record(rec) => rec.write(buf),
+            #[cfg(feature = "http2")]
+            Stream::H2(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "can't write to an HTTP/2 response body",
+            )),
         }
     }
     fn flush(&mut self) -> io::Result<()> {
@@ -56,29 +123,71 @@ impl Write for Stream {
             Stream::Http(sock) => sock.flush(),
             #[cfg(feature = "tls")]
             Stream::Https(stream) => stream.flush(),
+            Stream::Memory(..) => Ok(()),
+            #[cfg(feature = "vcr")]
+            Stream::Record(rec) => rec.flush(),
+            #[cfg(feature = "http2")]
+            Stream::H2(_) => Ok(()),
         }
     }
 }
 
-#[derive(Debug)]
+/// Options set on every new TCP connection.
+#[derive(Debug, Clone)]
+pub(crate) struct SocketOptions {
+    /// Idle time before keepalive probes, and the time between them.
+    pub keepalive: Option<Duration>,
+    pub nodelay: bool,
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub(crate) struct HostAddr<'a> {
     pub host: &'a str,
+    /// Set for IP literals, which are not resolved.
+    pub ip: Option<IpAddr>,
     pub port: u16,
 }
 
-pub(crate) fn connect_http(url: HostAddr) -> Result<(String, TcpStream), Error> {
+pub(crate) fn connect_http(
+    url: HostAddr,
+    resolver: Resolver,
+    timeout: Option<Duration>,
+    options: &SocketOptions,
+    timings: &mut Timings,
+) -> Result<(String, TcpStream), Error> {
     let host = url.host;
     let port = url.port;
 
-    let (name, ips) = dns(host)?;
-
-    let ipaddr = ips[0];
-    let socket = SocketAddr::new(ipaddr, port);
+    let (name, ips) = match url.ip {
+        Some(ip) => (host.to_string(), vec![ip]),
+        None => {
+            timings.dns_start = Some(Instant::now());
+            let resolved = resolver.resolve(host)?;
+            timings.dns_done = Some(Instant::now());
+            resolved
+        }
+    };
+    if ips.is_empty() {
+        return Err(ErrorKind::Dns.msg("No ip address for hostname"));
+    }
 
-    match connect_inner(socket) {
-        Ok(v) => Ok((name, v)),
-        Err(e) => Err(Error::from(e)),
+    // Try the addresses in turn, each with the full timeout.
+    let mut last_err = None;
+    for ipaddr in ips {
+        let socket = SocketAddr::new(ipaddr, port);
+        match connect_inner(socket, timeout, options) {
+            Ok(v) => return Ok((name, v)),
+            Err(e) => last_err = Some(e),
+        }
     }
+    let err = last_err.expect("at least one address was tried");
+    let kind = match err.kind() {
+        io::ErrorKind::TimedOut => ErrorKind::Timeout,
+        _ => ErrorKind::ConnectionFailed,
+    };
+    Err(kind.new().src(err))
 }
 
 #[cfg(feature = "tls")]
@@ -90,7 +199,7 @@ pub(crate) fn connect_https_v2(
     hostname: &str,
     agent: &Agent,
 ) -> Result<Stream, Error> {
-    let tls_conf: Arc<rustls::ClientConfig> = agent.tls_config.clone();
+    let tls_conf: Arc<rustls::ClientConfig> = agent.config.tls_config.clone();
     let mut sess = rustls::ClientConnection::new(
         tls_conf,
         rustls::ServerName::try_from(hostname).map_err(|_e| ErrorKind::Dns.new())?,
@@ -105,41 +214,34 @@ pub(crate) fn connect_https_v2(
     Ok(Stream::Https(Box::new(stream)))
 }
 
-pub fn dns(name: &str) -> io::Result<(String, IpAddrs)> {
-    let base = std::net::SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0));
-    let socket = UdpSocket::bind(base)?;
-    let addr = std::net::SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 53), 53));
-
-    let mut dmsg = Builder::new_query(13, true);
-    dmsg.add_question(name, false, QueryType::A, QueryClass::IN);
-    let dmsg = dmsg.build().expect("Bad DNS Query");
-
-    let c = socket.send_to(&dmsg, &addr)?;
-    assert!(c == dmsg.len(), "Incomplete dns message");
-    let mut buf = [0; 512];
-    let (amt, _) = socket.recv_from(&mut buf[..])?;
-    let buf = &buf[..amt];
-    let packet = Packet::parse(buf).expect("Failed to parse dns packet");
-    let q = packet
-        .questions
-        .first()
-        .expect("Question should never be empty");
-    let socks = packet
-        .answers
-        .iter()
-        .filter_map(|ans| match ans.data {
-            A(ipv4) => {
-                let addr = ipv4.0;
-                Some(std::net::IpAddr::V4(addr))
-            }
-            _ => None,
-        })
-        .collect();
-    Ok((q.qname.to_string(), socks))
-}
-
-fn connect_inner(socket: SocketAddr) -> io::Result<TcpStream> {
-    let tcp = TcpStream::connect(socket)?;
-    tcp.set_nodelay(true)?;
+fn connect_inner(
+    socket: SocketAddr,
+    timeout: Option<Duration>,
+    options: &SocketOptions,
+) -> io::Result<TcpStream> {
+    let tcp = match timeout {
+        Some(timeout) => TcpStream::connect_timeout(&socket, timeout)?,
+        None => TcpStream::connect(socket)?,
+    };
+    tcp.set_nodelay(options.nodelay)?;
+    let sock = SockRef::from(&tcp);
+    if let Some(time) = options.keepalive {
+        let keepalive = TcpKeepalive::new().with_time(time);
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux",
+            target_vendor = "apple",
+            windows,
+        ))]
+        let keepalive = keepalive.with_interval(time);
+        sock.set_tcp_keepalive(&keepalive)?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        sock.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        sock.set_recv_buffer_size(size)?;
+    }
     Ok(tcp)
 }
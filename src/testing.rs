@@ -0,0 +1,227 @@
+//! Canned responses for unit testing code that uses ureq, without a server.
+//!
+//! A [`Mock`] given to [`AgentBuilder::mock()`](crate::AgentBuilder::mock)
+//! answers the agent's requests instead of the network. Responses are
+//! looked up by method and url. Redirects, retries, middleware and cookies
+//! work as with a real server, so the code under test runs unchanged.
+//!
+//! ```
+//! use ureq::testing::{Mock, MockResponse};
+//!
+//! let mock = Mock::new().on(
+//!     "GET",
+//!     "http://example.com/user",
+//!     MockResponse::new(200)
+//!         .header("Content-Type", "application/json")
+//!         .body(r#"{"name":"Ferris"}"#),
+//! );
+//! let agent = ureq::AgentBuilder::new().mock(mock.clone()).build();
+//!
+//! let url = ureq::Url::parse("http://example.com/user")?;
+//! let body = agent.get(url).call()?.into_string()?;
+//! assert_eq!(body, r#"{"name":"Ferris"}"#);
+//! assert_eq!(mock.requests()[0].method, "GET");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::body::SizedReader;
+use crate::error::{Error, ErrorKind};
+use crate::request::Request;
+use crate::response::Response;
+use crate::stream::Stream;
+use crate::timings::Timings;
+use crate::unit::request_headers;
+
+/// Answers requests with canned responses, see the [module docs](self).
+///
+/// Clones share the responses and the requests received, so a test can
+/// keep one to check what was sent.
+#[derive(Clone, Default)]
+pub struct Mock {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    routes: Vec<Route>,
+    requests: Vec<MockRequest>,
+}
+
+struct Route {
+    method: String,
+    url: String,
+    responses: VecDeque<MockResponse>,
+}
+
+/// A request received by a [`Mock`].
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    pub url: String,
+    /// The headers as they would have been sent, including `Host` and
+    /// `User-Agent`.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// A canned response, or error, for a [`Mock`].
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    delay: Option<Duration>,
+    error: Option<ErrorKind>,
+}
+
+impl Mock {
+    pub fn new() -> Self {
+        Mock::default()
+    }
+
+    /// Answer `method` requests to `url` with `response`.
+    ///
+    /// Adding several responses for the same method and url gives them
+    /// out in order, the last one repeats. That way retries can be
+    /// tested, e.g. with a `503` followed by a `200`.
+    pub fn on(self, method: &str, url: &str, response: MockResponse) -> Self {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let found = inner
+                .routes
+                .iter_mut()
+                .find(|r| r.method == method && r.url == url);
+            match found {
+                Some(route) => route.responses.push_back(response),
+                None => inner.routes.push(Route {
+                    method: method.to_string(),
+                    url: url.to_string(),
+                    responses: vec![response].into(),
+                }),
+            }
+        }
+        self
+    }
+
+    /// The requests received so far, in order.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.inner.lock().unwrap().requests.clone()
+    }
+
+    pub(crate) fn call(&self, req: &Request, mut body: SizedReader) -> Result<Response, Error> {
+        let headers = request_headers(req, body.size, body.bytes)?
+            .into_iter()
+            .map(|(n, v)| (n.into_owned(), v.into_owned()))
+            .collect();
+        let mut bytes = vec![];
+        body.reader.read_to_end(&mut bytes)?;
+        let url = req.url.to_string();
+
+        let response = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.requests.push(MockRequest {
+                method: req.method.to_string(),
+                url: url.clone(),
+                headers,
+                body: bytes,
+            });
+            let route = inner
+                .routes
+                .iter_mut()
+                .find(|r| r.method == req.method && r.url == url)
+                .ok_or_else(|| ErrorKind::ConnectionFailed.msg("No mock response for request"))?;
+            match route.responses.len() {
+                1 => route.responses[0].clone(),
+                _ => route.responses.pop_front().unwrap(),
+            }
+        };
+
+        if let Some(delay) = response.delay {
+            // a delay beyond the read timeout is one
+            match req.timeout_read {
+                Some(timeout) if timeout < delay => {
+                    thread::sleep(timeout);
+                    return Err(ErrorKind::Timeout.msg("Mock response delayed past timeout"));
+                }
+                _ => thread::sleep(delay),
+            }
+        }
+        if let Some(kind) = response.error {
+            return Err(kind.msg("Mock error"));
+        }
+
+        let config = &req.agent.config;
+        let mut resp = Response::do_from_stream(
+            Stream::from_vec(response.to_bytes()),
+            Timings::new(),
+            config.max_response_header_size,
+            config.lenient_headers,
+            config.wire_log.as_ref(),
+        )?;
+        resp.is_head = req.method == "HEAD";
+        Ok(resp)
+    }
+}
+
+impl MockResponse {
+    /// A response with status `status` and an empty body.
+    pub fn new(status: u16) -> Self {
+        MockResponse {
+            status,
+            headers: vec![],
+            body: vec![],
+            delay: None,
+            error: None,
+        }
+    }
+
+    /// The request fails with an error of `kind` rather than getting a
+    /// response, e.g. [`ErrorKind::ConnectionFailed`].
+    pub fn error(kind: ErrorKind) -> Self {
+        MockResponse {
+            error: Some(kind),
+            ..MockResponse::new(0)
+        }
+    }
+
+    /// Add a header. `Content-Length` is set from the body unless added.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Wait before answering. A delay longer than the request's read
+    /// timeout is an [`ErrorKind::Timeout`] error.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = format!("HTTP/1.1 {} Mock\r\n", self.status).into_bytes();
+        for (name, value) in &self.headers {
+            out.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        let has_length = self.headers.iter().any(|(name, _)| {
+            name.eq_ignore_ascii_case("content-length")
+                || name.eq_ignore_ascii_case("transfer-encoding")
+        });
+        if !has_length {
+            out.extend_from_slice(format!("Content-Length: {}\r\n", self.body.len()).as_bytes());
+        }
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&self.body);
+        out
+    }
+}
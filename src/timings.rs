@@ -0,0 +1,93 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// When each step of a request happened, to tell whether time went to
+/// DNS, connecting or the server.
+///
+/// The steps are durations since the request started sending. Steps that
+/// didn't happen are `None`: DNS for IP addresses, DNS, connect and TLS for
+/// connections reused from the pool, TLS for plain http. With retries or
+/// redirects these are the timings of the attempt that gave the response.
+///
+/// ```no_run
+/// let url = ureq::Url::parse("https://example.com/")?;
+/// let resp = ureq::get(url).call()?;
+/// let timings = resp.timings();
+/// println!("connected after {:?}", timings.connect_done());
+/// println!("first byte after {:?}", timings.first_byte());
+/// # Ok::<(), ureq::Error>(())
+/// ```
+#[derive(Clone, Copy)]
+pub struct Timings {
+    start: Instant,
+    pub(crate) dns_start: Option<Instant>,
+    pub(crate) dns_done: Option<Instant>,
+    pub(crate) connect_done: Option<Instant>,
+    pub(crate) tls_done: Option<Instant>,
+    pub(crate) first_byte: Option<Instant>,
+    pub(crate) body_done: Option<Instant>,
+}
+
+impl Timings {
+    pub(crate) fn new() -> Self {
+        Timings {
+            start: Instant::now(),
+            dns_start: None,
+            dns_done: None,
+            connect_done: None,
+            tls_done: None,
+            first_byte: None,
+            body_done: None,
+        }
+    }
+
+    /// When the request started.
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    pub fn dns_start(&self) -> Option<Duration> {
+        self.since_start(self.dns_start)
+    }
+
+    pub fn dns_done(&self) -> Option<Duration> {
+        self.since_start(self.dns_done)
+    }
+
+    /// The TCP connection is established, through the tunnel with a proxy.
+    pub fn connect_done(&self) -> Option<Duration> {
+        self.since_start(self.connect_done)
+    }
+
+    pub fn tls_done(&self) -> Option<Duration> {
+        self.since_start(self.tls_done)
+    }
+
+    /// The first byte of the response arrived.
+    pub fn first_byte(&self) -> Option<Duration> {
+        self.since_start(self.first_byte)
+    }
+
+    /// The body was read to the end. Only known through
+    /// [`ResponseReader::timings()`](crate::ResponseReader::timings).
+    pub fn body_done(&self) -> Option<Duration> {
+        self.since_start(self.body_done)
+    }
+
+    fn since_start(&self, at: Option<Instant>) -> Option<Duration> {
+        at.map(|at| at.duration_since(self.start))
+    }
+}
+
+impl fmt::Debug for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Timings")
+            .field("dns_start", &self.dns_start())
+            .field("dns_done", &self.dns_done())
+            .field("connect_done", &self.connect_done())
+            .field("tls_done", &self.tls_done())
+            .field("first_byte", &self.first_byte())
+            .field("body_done", &self.body_done())
+            .finish()
+    }
+}
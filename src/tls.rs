@@ -0,0 +1,123 @@
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use once_cell::sync::Lazy;
+
+use crate::error::{Error, ErrorKind};
+
+/// The TLS configuration of agents that don't set their own.
+pub(crate) static TLS_CONFIG: Lazy<Arc<rustls::ClientConfig>> =
+    Lazy::new(|| client_config(&[], true));
+
+/// A root certificate to trust for https, in addition to or instead of the
+/// default ones, e.g. the one of a private CA.
+///
+/// ```no_run
+/// let pem = std::fs::read("my-ca.pem")?;
+/// let mut builder = ureq::AgentBuilder::new();
+/// for cert in ureq::Certificate::from_pem(&pem)? {
+///     builder = builder.root_certificate(cert);
+/// }
+/// let agent = builder.build();
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Certificate(rustls::Certificate);
+
+impl Certificate {
+    /// A certificate in DER form.
+    ///
+    /// One that isn't a valid X.509 certificate is an
+    /// [`ErrorKind::InvalidCertificate`] error.
+    pub fn from_der(der: &[u8]) -> Result<Certificate, Error> {
+        let cert = rustls::Certificate(der.to_vec());
+        // parsed here, so that a bad one doesn't go unnoticed until a request
+        rustls::RootCertStore::empty()
+            .add(&cert)
+            .map_err(|_| ErrorKind::InvalidCertificate.msg("Malformed DER certificate"))?;
+        Ok(Certificate(cert))
+    }
+
+    /// The certificates of a PEM file, ignoring anything but
+    /// `CERTIFICATE` sections.
+    pub fn from_pem(pem: &[u8]) -> Result<Vec<Certificate>, Error> {
+        let ders = rustls_pemfile::certs(&mut BufReader::new(pem))
+            .map_err(|e| ErrorKind::InvalidCertificate.msg("Malformed PEM").src(e))?;
+        ders.iter().map(|der| Certificate::from_der(der)).collect()
+    }
+}
+
+/// A client config trusting `roots`, and the default ones if `default_roots`.
+pub(crate) fn client_config(
+    roots: &[Certificate],
+    default_roots: bool,
+) -> Arc<rustls::ClientConfig> {
+    let mut root_store = rustls::RootCertStore::empty();
+    if default_roots {
+        add_default_roots(&mut root_store);
+    }
+    for cert in roots {
+        // can't fail, from_der checked it
+        let _ = root_store.add(&cert.0);
+    }
+
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+#[cfg(not(feature = "native-roots"))]
+fn add_default_roots(root_store: &mut rustls::RootCertStore) {
+    root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+}
+
+// The root certificates the OS trusts, including ones installed by an
+// administrator. Those that can't be loaded or parsed are skipped, so a
+// broken store means https requests fail with an unknown issuer.
+#[cfg(feature = "native-roots")]
+fn add_default_roots(root_store: &mut rustls::RootCertStore) {
+    let certs = rustls_native_certs::load_native_certs().unwrap_or_default();
+    let ders: Vec<Vec<u8>> = certs.into_iter().map(|c| c.0).collect();
+    root_store.add_parsable_certificates(&ders);
+}
+
+/// A client config leaving the server certificate to `verifier`.
+pub(crate) fn verifier_client_config(
+    verifier: Arc<dyn rustls::client::ServerCertVerifier>,
+) -> Arc<rustls::ClientConfig> {
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+/// Accepts any server certificate, for
+/// [`AgentBuilder::danger_accept_invalid_certs()`](crate::AgentBuilder::danger_accept_invalid_certs).
+///
+/// The handshake signatures are still checked against the certificate's
+/// key, only the certificate itself isn't.
+pub(crate) struct NoVerifier;
+
+impl rustls::client::ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
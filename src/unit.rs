@@ -1,67 +1,266 @@
-use std::io::{Result as IoResult, Write};
+use std::borrow::Cow;
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Instant;
 
 #[cfg(feature = "tls")]
 use crate::url::Scheme;
-use crate::url::Url;
 
-use crate::agent::Agent;
-use crate::error::Error;
+use crate::body::{BodySize, SizedReader};
+use crate::error::{Error, ErrorKind};
+use crate::header::{valid_name, valid_value};
+use crate::pool::PoolKey;
+use crate::proxy::connect_tunnel;
+use crate::request::Request;
+use crate::sign::SignRequest;
 #[cfg(feature = "tls")]
 use crate::stream::connect_https_v2;
 use crate::stream::{connect_http, HostAddr, Stream};
+use crate::timings::Timings;
+use crate::url::decode;
 
 /// Send request line + headers (all up until the body).
 pub(crate) fn send_request(
-    host: &str,
-    path: &str,
-    user_agent: &str,
-    stream: &mut Stream,
-) -> IoResult<()> {
+    req: &Request,
+    body: &SizedReader,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    let mut buf = Vec::with_capacity(512);
+
     // request line
-    let mut buf = [0; 512];
-    let mut v = &mut buf[..];
+    buf.extend_from_slice(req.method.as_bytes());
+    buf.extend_from_slice(b" ");
+    buf.extend_from_slice(req.url.request_target().as_bytes());
+    buf.extend_from_slice(b" HTTP/1.1\r\n");
 
-    let _ = v.write(b"GET ");
-    let _ = v.write(path.as_bytes());
-    let _ = v.write(b" HTTP/1.1\r\n");
+    for (name, value) in request_headers(req, body.size, body.bytes)? {
+        write_header(&mut buf, &name, &value);
+    }
 
-    // host header if not set by user.
-    let _ = v.write(b"Host: ");
-    let _ = v.write(host.as_bytes());
-    let _ = v.write(b"\r\n");
+    // finish
+    buf.extend_from_slice(b"\r\n");
 
-    let _ = v.write(b"User-Agent: ");
-    let _ = v.write(user_agent.as_bytes());
-    let _ = v.write(b"\r\n");
+    if let Some(wire_log) = &req.agent.config.wire_log {
+        wire_log.request(&buf);
+    }
+    stream.write_all(&buf)?;
+    Ok(())
+}
 
-    // finish
+/// A header to send, as name and value.
+pub(crate) type Header<'a> = (Cow<'a, str>, Cow<'a, str>);
+
+/// The headers sent with `req`: those the user set and those derived from
+/// the agent, the url and the body. Signed if the agent has a signer.
+pub(crate) fn request_headers<'a>(
+    req: &'a Request,
+    body_size: BodySize,
+    body: Option<&[u8]>,
+) -> Result<Vec<Header<'a>>, Error> {
+    let mut headers: Vec<Header<'a>> = vec![];
+
+    // Host and User-Agent go first, the user's values replacing the defaults
+    let host = match req.header("Host") {
+        Some(host) => host.into(),
+        None => req.url.host_header().into(),
+    };
+    headers.push(("Host".into(), host));
+    let user_agent = req
+        .header("User-Agent")
+        .or(req.agent.config.user_agent.as_deref());
+    if let Some(user_agent) = user_agent {
+        headers.push(("User-Agent".into(), user_agent.into()));
+    }
 
-    let _ = v.write(b"\r\n");
-    let rem = v.len();
+    for (name, value) in &req.headers {
+        if name.eq_ignore_ascii_case("Host") || name.eq_ignore_ascii_case("User-Agent") {
+            continue;
+        }
+        headers.push((name.into(), value.as_str().into()));
+    }
 
-    stream.write_all(&buf[..(512-rem)])
+    // credentials in the url unless the user set them explicitly
+    let (user, pass) = (req.url.username(), req.url.password());
+    if (!user.is_empty() || pass.is_some()) && req.header("Authorization").is_none() {
+        let auth = basic_auth(&decode(user), &decode(pass.unwrap_or("")));
+        headers.push(("Authorization".into(), auth.into()));
+    }
+
+    // cookies from the jar unless the user set them explicitly
+    #[cfg(feature = "cookies")]
+    if req.header("Cookie").is_none() {
+        let jar = req.agent.state.cookie_store.lock().unwrap();
+        if let Some(cookies) = jar.cookie_header(&req.url) {
+            headers.push(("Cookie".into(), cookies.into()));
+        }
+    }
+
+    // a user set Content-Length wins, see Request::do_call
+    if req.header("Content-Length").is_none() {
+        match body_size {
+            BodySize::Empty => {}
+            BodySize::Unknown => headers.push(("Transfer-Encoding".into(), "chunked".into())),
+            BodySize::Known(size) => {
+                headers.push(("Content-Length".into(), size.to_string().into()))
+            }
+        }
+    }
+
+    // signed last, over the headers as they will be sent
+    if let Some(signer) = &req.agent.config.signer {
+        let signed = signer(&SignRequest {
+            method: &req.method,
+            url: &req.url,
+            headers: &headers,
+            body,
+        });
+        for (name, value) in signed {
+            if !valid_name(&name) || !valid_value(&value) {
+                return Err(ErrorKind::BadHeader.msg("Invalid header name or value from signer"));
+            }
+            headers.retain(|(n, _)| !n.eq_ignore_ascii_case(&name));
+            headers.push((name.into(), value.into()));
+        }
+    }
+
+    Ok(headers)
 }
 
-#[cfg(not(feature = "tls"))]
-pub(crate) fn connect(_agent: &Agent, url: &Url) -> Result<Stream, Error> {
-    let h = HostAddr {
-        host: url.host_str(),
-        port: url.port(),
+/// The `Authorization` header value for HTTP Basic authentication, RFC 7617.
+pub(crate) fn basic_auth(user: &str, pass: &str) -> String {
+    format!("Basic {}", base64(format!("{}:{}", user, pass).as_bytes()))
+}
+
+// Standard alphabet with padding, RFC 4648 4. Not worth a dependency.
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn write_header(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    buf.extend_from_slice(b": ");
+    buf.extend_from_slice(value.as_bytes());
+    buf.extend_from_slice(b"\r\n");
+}
+
+fn connect_tcp(req: &Request, timings: &mut Timings) -> Result<(String, TcpStream), Error> {
+    let config = &req.agent.config;
+    let connect_to = req.connect_addr();
+    let h = match (&config.proxy, connect_to) {
+        (Some(proxy), _) => HostAddr {
+            host: proxy.host(),
+            ip: proxy.ip(),
+            port: proxy.port(),
+        },
+        // not resolved, but still named by the host for TLS
+        (None, Some(addr)) => HostAddr {
+            host: req.url.host_str(),
+            ip: Some(addr.ip()),
+            port: addr.port(),
+        },
+        (None, None) => HostAddr {
+            host: req.url.host_str(),
+            ip: req.url.host_ip(),
+            port: req.url.port(),
+        },
     };
-    let (_, s) = connect_http(h)?;
+    let connect = |timings: &mut Timings| {
+        let (name, stream) = connect_http(
+            h,
+            config.resolver,
+            req.timeout_connect,
+            &config.socket_options,
+            timings,
+        )?;
+        // set before a TLS handshake, which is subject to them as well
+        stream.set_read_timeout(req.timeout_read)?;
+        stream.set_write_timeout(req.timeout_write)?;
+        Ok::<_, Error>((name, stream))
+    };
+    let (name, mut stream) = connect(timings)?;
+
+    if let Some(proxy) = &config.proxy {
+        let target = match connect_to {
+            Some(addr) => addr.to_string(),
+            None => format!("{}:{}", req.url.host_str(), req.url.port()),
+        };
+        let mut reconnect = || Ok(connect(&mut Timings::new())?.1);
+        connect_tunnel(
+            &mut stream,
+            proxy,
+            &target,
+            config.user_agent.as_deref(),
+            &mut reconnect,
+        )?;
+        timings.connect_done = Some(Instant::now());
+        // TLS is with the server at the end of the tunnel
+        return Ok((req.url.host_str().to_string(), stream));
+    }
+    timings.connect_done = Some(Instant::now());
+    Ok((name, stream))
+}
+
+/// A connection from the agent's pool, or a new one, with the time it was
+/// established and whether it is from the pool.
+pub(crate) fn connect(
+    req: &Request,
+    timings: &mut Timings,
+) -> Result<(Stream, Instant, bool), Error> {
+    let key = PoolKey::new(req);
+    if let Some((stream, created)) = req.agent.state.pool.try_get_connection(&key) {
+        if let Some(sock) = stream.socket() {
+            sock.set_read_timeout(req.timeout_read)?;
+            sock.set_write_timeout(req.timeout_write)?;
+        }
+        return Ok((stream, created, true));
+    }
+    Ok((connect_new(req, timings)?, Instant::now(), false))
+}
+
+/// Open a connection for `req` and leave it in the pool, without sending
+/// anything.
+pub(crate) fn preconnect(req: &Request) -> Result<(), Error> {
+    let stream = connect_new(req, &mut Timings::new())?;
+    req.agent
+        .state
+        .pool
+        .add(PoolKey::new(req), stream, Instant::now(), None);
+    Ok(())
+}
+
+#[cfg(not(feature = "tls"))]
+pub(crate) fn connect_new(req: &Request, timings: &mut Timings) -> Result<Stream, Error> {
+    let (_, s) = connect_tcp(req, timings)?;
     Ok(Stream::Http(s))
 }
 
 #[cfg(feature = "tls")]
-pub(crate) fn connect(agent: &Agent, url: &Url) -> Result<Stream, Error> {
-    let h = HostAddr {
-        host: url.host_str(),
-        port: url.port(),
-    };
-    let (name, stream) = connect_http(h)?;
-    let s = match url.scheme() {
+pub(crate) fn connect_new(req: &Request, timings: &mut Timings) -> Result<Stream, Error> {
+    let (name, stream) = connect_tcp(req, timings)?;
+    let s = match req.url.scheme() {
         Scheme::Http => Stream::Http(stream),
-        Scheme::Https => connect_https_v2(stream, &name, agent)?,
+        Scheme::Https => {
+            let s = connect_https_v2(stream, &name, &req.agent)?;
+            timings.tls_done = Some(Instant::now());
+            s
+        }
     };
     Ok(s)
 }
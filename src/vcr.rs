@@ -0,0 +1,256 @@
+//! Record and replay of HTTP exchanges for deterministic tests.
+//!
+//! With a [`Cassette`] installed, the first run of a request performs it for
+//! real and writes the raw exchange to the cassette directory. Later runs
+//! find the recording by request fingerprint and replay it without touching
+//! the network.
+//!
+//! Each exchange is stored as two files named after the fingerprint:
+//! `<fingerprint>.req` holds the request as sent, `<fingerprint>.http`
+//! holds the raw response bytes (status line, headers and body). A response
+//! is saved when it is dropped, with whatever of the body was read by then,
+//! so read bodies to the end while recording.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use once_cell::sync::OnceCell;
+
+use crate::body::{send_body, SizedReader};
+use crate::error::{Error, ErrorKind};
+use crate::request::Request;
+use crate::response::Response;
+use crate::stream::Stream;
+use crate::timings::Timings;
+use crate::unit::{connect, send_request};
+
+static CASSETTE: OnceCell<Cassette> = OnceCell::new();
+
+/// Hook rewriting recorded bytes before they are fingerprinted or stored.
+type Redactor = Box<dyn Fn(&mut Vec<u8>) + Send + Sync + 'static>;
+
+/// How a [`Cassette`] treats requests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VcrMode {
+    /// Replay when a recording exists, otherwise perform and record.
+    Auto,
+    /// Always perform the request, overwriting any earlier recording.
+    Record,
+    /// Only replay. A request without a recording fails.
+    Replay,
+}
+
+/// A directory of recorded exchanges.
+///
+/// Requests are matched by all they send, the body included. The random
+/// boundary of a multipart form is left out, but anything else that
+/// changes from run to run, like a timestamp or a nonce in the body, makes
+/// for a request that is never replayed. Replace it with
+/// [`redact()`](Cassette::redact).
+pub struct Cassette {
+    dir: PathBuf,
+    mode: VcrMode,
+    redact: Vec<Redactor>,
+}
+
+impl Cassette {
+    /// A cassette in `dir` using [`VcrMode::Auto`].
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Cassette {
+            dir: dir.into(),
+            mode: VcrMode::Auto,
+            redact: vec![],
+        }
+    }
+
+    /// Set the record/replay mode.
+    pub fn mode(mut self, mode: VcrMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Add a hook that scrubs secrets from recorded bytes.
+    ///
+    /// Hooks run over the request before it is fingerprinted and
+    /// over both request and response before they are written to disk,
+    /// so a redacted token neither leaks into the cassette nor changes
+    /// which recording a request maps to.
+    ///
+    /// ```no_run
+    /// ureq::Cassette::new("tests/cassettes")
+    ///     .redact(|bytes| {
+    ///         let text = String::from_utf8_lossy(bytes).replace("s3cr3t", "REDACTED");
+    ///         *bytes = text.into_bytes();
+    ///     })
+    ///     .install();
+    /// ```
+    pub fn redact(mut self, f: impl Fn(&mut Vec<u8>) + Send + Sync + 'static) -> Self {
+        self.redact.push(Box::new(f));
+        self
+    }
+
+    /// Route all requests through this cassette.
+    ///
+    /// Only the first installed cassette takes effect, a later one is
+    /// handed back as the error.
+    pub fn install(self) -> std::result::Result<(), Cassette> {
+        CASSETTE.set(self)
+    }
+
+    fn scrub(&self, mut bytes: Vec<u8>) -> Vec<u8> {
+        for f in &self.redact {
+            f(&mut bytes);
+        }
+        bytes
+    }
+
+    fn file(&self, fingerprint: &str, ext: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", fingerprint, ext))
+    }
+
+    pub(crate) fn call(&'static self, req: &Request, body: SizedReader) -> Result<Response, Error> {
+        let mut sent = vec![];
+        send_request(req, &body, &mut sent)?;
+        send_body(body, &mut sent)?;
+        let fingerprint = fingerprint(&self.scrub(fixed_boundary(&sent)));
+        let recorded = self.file(&fingerprint, "http");
+
+        let replay = match self.mode {
+            VcrMode::Auto => recorded.exists(),
+            VcrMode::Record => false,
+            VcrMode::Replay => true,
+        };
+
+        if replay {
+            let bytes = fs::read(&recorded).map_err(|e| {
+                ErrorKind::ConnectionFailed
+                    .msg("No recorded exchange for request")
+                    .src(e)
+            })?;
+            let config = &req.agent.config;
+            let stream = Stream::from_vec(bytes);
+            return Response::do_from_stream(
+                stream,
+                Timings::new(),
+                config.max_response_header_size,
+                config.lenient_headers,
+                config.wire_log.as_ref(),
+            );
+        }
+
+        let mut timings = Timings::new();
+        let mut stream = Stream::Record(Box::new(Recorder {
+            cassette: self,
+            fingerprint,
+            inner: connect(req, &mut timings)?.0,
+            request: vec![],
+            response: vec![],
+        }));
+        stream.write_all(&sent)?;
+        let config = &req.agent.config;
+        Response::do_from_stream(
+            stream,
+            timings,
+            config.max_response_header_size,
+            config.lenient_headers,
+            config.wire_log.as_ref(),
+        )
+    }
+}
+
+/// The cassette installed with [`Cassette::install`], if any.
+pub(crate) fn cassette() -> Option<&'static Cassette> {
+    CASSETTE.get()
+}
+
+/// Stream wrapper that captures both directions and saves them on drop.
+pub struct Recorder {
+    cassette: &'static Cassette,
+    fingerprint: String,
+    pub(crate) inner: Stream,
+    request: Vec<u8>,
+    response: Vec<u8>,
+}
+
+impl Recorder {
+    fn save(&mut self) -> io::Result<()> {
+        let c = self.cassette;
+        fs::create_dir_all(&c.dir)?;
+        let request = c.scrub(std::mem::take(&mut self.request));
+        let response = c.scrub(std::mem::take(&mut self.response));
+        fs::write(c.file(&self.fingerprint, "req"), request)?;
+        fs::write(c.file(&self.fingerprint, "http"), response)
+    }
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.response.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl Write for Recorder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.request.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // A failed save only means the next run records again.
+        let _ = self.save();
+    }
+}
+
+// The request with the random boundary of a multipart body replaced by a
+// fixed one, so the same form always has the same fingerprint.
+fn fixed_boundary(sent: &[u8]) -> Vec<u8> {
+    let head_end = sent
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .unwrap_or(sent.len());
+    let head = String::from_utf8_lossy(&sent[..head_end]);
+    let boundary = head.split("\r\n").find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if !name.trim().eq_ignore_ascii_case("content-type") {
+            return None;
+        }
+        let (_, boundary) = value.split_once("boundary=")?;
+        let boundary = boundary.split(';').next()?.trim();
+        Some(boundary.trim_matches('"').to_string())
+    });
+    let boundary = match boundary {
+        Some(b) if !b.is_empty() => b.into_bytes(),
+        _ => return sent.to_vec(),
+    };
+    let mut out = Vec::with_capacity(sent.len());
+    let mut rest = sent;
+    while !rest.is_empty() {
+        if rest.starts_with(&boundary) {
+            out.extend_from_slice(b"boundary");
+            rest = &rest[boundary.len()..];
+        } else {
+            out.push(rest[0]);
+            rest = &rest[1..];
+        }
+    }
+    out
+}
+
+// FNV-1a, chosen over std's hasher because file names must stay stable
+// across compiler versions.
+fn fingerprint(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
@@ -0,0 +1,69 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+/// A head as written to or read from the connection, for
+/// [`AgentBuilder::wire_log()`](crate::AgentBuilder::wire_log).
+///
+/// The text is the status or request line and headers as they are on
+/// the wire, with the CRLF line ends and the empty line ending the head.
+/// Bodies are not logged. For HTTP/2 the pseudo headers, like `:method`
+/// and `:status`, come first, and there are no request or status lines.
+#[derive(Debug)]
+pub enum Wire<'a> {
+    /// A request head as it was written.
+    Request(&'a str),
+    /// A response head as it was read, including interim `1xx` responses.
+    Response(&'a str),
+}
+
+pub(crate) type WireLogFn = dyn Fn(Wire) + Send + Sync;
+
+// Header values replaced when redacting, they carry credentials.
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+#[derive(Clone)]
+pub(crate) struct WireLog {
+    pub log: Arc<WireLogFn>,
+    pub redact: bool,
+}
+
+impl WireLog {
+    pub fn request(&self, head: &[u8]) {
+        let head = self.text(head);
+        (self.log)(Wire::Request(&head));
+    }
+
+    pub fn response(&self, head: &[u8]) {
+        let head = self.text(head);
+        (self.log)(Wire::Response(&head));
+    }
+
+    fn text<'a>(&self, head: &'a [u8]) -> Cow<'a, str> {
+        let head = String::from_utf8_lossy(head);
+        if !self.redact {
+            return head;
+        }
+        let mut out = String::with_capacity(head.len());
+        for line in head.split_inclusive('\n') {
+            match line.split_once(':') {
+                Some((name, value)) if is_secret(name.trim()) => {
+                    let kept = value.trim_end_matches(&['\r', '\n'][..]).len();
+                    out.push_str(name);
+                    out.push_str(": [redacted]");
+                    out.push_str(&value[kept..]);
+                }
+                _ => out.push_str(line),
+            }
+        }
+        out.into()
+    }
+}
+
+pub(crate) fn is_secret(name: &str) -> bool {
+    SECRET_HEADERS.iter().any(|s| s.eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,43 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+
+// Answers every request on every connection with `response`.
+fn serve(response: String) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let response = response.clone();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    match reader.read_line(&mut line) {
+                        Ok(0) | Err(_) => return,
+                        Ok(_) if line == "\r\n" => {
+                            stream.write_all(response.as_bytes()).unwrap();
+                        }
+                        Ok(_) => {}
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+#[test]
+fn connect_to_is_dropped_on_redirect_to_other_port() {
+    let other = serve("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nother".to_string());
+    let pinned = serve(format!(
+        "HTTP/1.1 302 Found\r\nLocation: http://127.0.0.1:{}/\r\nContent-Length: 0\r\n\r\n",
+        other.port()
+    ));
+
+    let url = ureq::Url::parse(&format!("http://127.0.0.1:{}/", pinned.port())).unwrap();
+    let resp = ureq::get(url).connect_to(pinned).call().unwrap();
+    assert_eq!(resp.into_string().unwrap(), "other");
+}